@@ -0,0 +1,54 @@
+use advent_of_code_2019::intcode::{asm, empty, Engine, IntcodeMachine};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+/// Counts down from a value read from input, summing the counter into an
+/// accumulator, so nearly all the time is spent in a four instruction loop.
+const COUNTDOWN: &str = "
+        input pos(n)
+loop:   add pos(sum), pos(n), pos(sum)
+        add pos(n), imm(-1), pos(n)
+        jit pos(n), imm(loop)
+        output pos(sum)
+        halt
+n:      .data 0
+sum:    .data 0
+";
+
+/// Outputs a copy of itself, exercising the relative base.
+const QUINE: &str = "109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99";
+
+fn engines(c: &mut Criterion) {
+    let countdown = asm::assemble(COUNTDOWN).unwrap();
+    let quine: Vec<isize> = QUINE.split(',').map(|n| n.parse().unwrap()).collect();
+
+    let mut group = c.benchmark_group("engine");
+
+    for &(name, engine) in &[
+        ("interpreter", Engine::Interpreter),
+        ("cached", Engine::Cached),
+        ("threaded", Engine::Threaded),
+    ] {
+        group.bench_function(BenchmarkId::new("countdown", name), |b| {
+            b.iter(|| {
+                let mut machine = IntcodeMachine::new(&countdown, vec![10_000].into_iter(), 0)
+                    .with_engine(engine);
+                machine.run().unwrap();
+                *machine.output_mut()
+            })
+        });
+
+        group.bench_function(BenchmarkId::new("quine", name), |b| {
+            b.iter(|| {
+                let mut machine =
+                    IntcodeMachine::new(&quine, empty(), Vec::new()).with_engine(engine);
+                machine.run().unwrap();
+                machine.output_mut().len()
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, engines);
+criterion_main!(benches);
@@ -20,7 +20,7 @@ fn part1(bytes: &[isize]) -> isize {
     bytes[2] = 2;
 
     let mut machine = IntcodeMachine::new(&bytes, None.into_iter(), ());
-    machine.run();
+    machine.run().unwrap();
 
     machine.data()[0]
 }
@@ -41,7 +41,7 @@ fn part2(bytes: &[isize]) -> String {
             mbytes[2] = verb;
 
             let mut machine = IntcodeMachine::new(&mbytes, &mut iter, ());
-            machine.run();
+            machine.run().unwrap();
 
             if mbytes[0] == desired {
                 return format!(
@@ -17,7 +17,7 @@ fn part1(input: &[isize]) -> isize {
     let mut output = 0isize;
 
     let mut machine = IntcodeMachine::new(&bytes, once(1), &mut output);
-    machine.run();
+    machine.run().unwrap();
 
     output
 }
@@ -28,7 +28,7 @@ fn part2(input: &[isize]) -> isize {
     let mut output = 0isize;
 
     let mut machine = IntcodeMachine::new(&bytes, once(5), &mut output);
-    machine.run();
+    machine.run().unwrap();
 
     output
 }
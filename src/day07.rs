@@ -1,11 +1,6 @@
-#![allow(clippy::many_single_char_names)]
-
-use crate::intcode::*;
+use crate::intcode::topology::{Domain, Topology};
 use aoc_runner_derive::aoc;
-use crossbeam_channel::bounded;
-use itertools::Itertools;
-use rayon::prelude::*;
-use std::iter::once;
+use std::ops::RangeInclusive;
 
 fn parse_input(input: &str) -> Vec<isize> {
     input
@@ -15,90 +10,24 @@ fn parse_input(input: &str) -> Vec<isize> {
         .unwrap()
 }
 
-#[aoc(day7, part1)]
-fn part1_overengineered(input: &str) -> isize {
-    let code = parse_input(input);
-
-    let inputs: Vec<(isize, isize, isize, isize, isize)> = (0..=4)
-        .permutations(5)
-        .map(|v| (v[0], v[1], v[2], v[3], v[4]))
-        .collect();
-
-    inputs
-        .into_par_iter()
-        .map(|ins| {
-            let (a, b, c, d, e) = ins;
-            let (a_out, b_in) = bounded(1);
-            let (b_out, c_in) = bounded(1);
-            let (c_out, d_in) = bounded(1);
-            let (d_out, e_in) = bounded(1);
-            let mut e_out = 0isize;
+/// The highest signal the last amplifier of `topology` sends for any order
+/// of `phases`.
+fn max_signal(code: &[isize], topology: Topology, phases: RangeInclusive<isize>) -> isize {
+    let last_signal = |outputs: &[Vec<isize>]| *outputs.last().unwrap().last().unwrap();
+    let domain = Domain::Permutations(phases.collect());
 
-            let mut a = IntcodeMachine::new(&code, once(a).chain(once(0)), a_out);
-            let mut b = IntcodeMachine::new(&code, once(b).chain(b_in), b_out);
-            let mut c = IntcodeMachine::new(&code, once(c).chain(c_in), c_out);
-            let mut d = IntcodeMachine::new(&code, once(d).chain(d_in), d_out);
-            let mut e = IntcodeMachine::new(&code, once(e).chain(e_in), &mut e_out);
-
-            let mut pool = scoped_threadpool::Pool::new(5);
-
-            pool.scoped(|scope| {
-                scope.execute(|| a.run());
-                scope.execute(|| b.run());
-                scope.execute(|| c.run());
-                scope.execute(|| d.run());
-                scope.execute(|| e.run());
-            });
+    let best = topology.search(code, &[0], &domain, last_signal).unwrap();
+    best.unwrap().key
+}
 
-            e_out
-        })
-        .max()
-        .unwrap()
+#[aoc(day7, part1)]
+fn part1_overengineered(input: &str) -> isize {
+    max_signal(&parse_input(input), Topology::chain(5), 0..=4)
 }
 
 #[aoc(day7, part2)]
 fn part2_overengineered(input: &str) -> isize {
-    let code = parse_input(input);
-
-    let inputs: Vec<(isize, isize, isize, isize, isize)> = (5..=9)
-        .permutations(5)
-        .map(|v| (v[0], v[1], v[2], v[3], v[4]))
-        .collect();
-
-    inputs
-        .into_par_iter()
-        .map(|ins| {
-            let (a, b, c, d, e) = ins;
-            let (a_out, b_in) = bounded(1);
-            let (b_out, c_in) = bounded(1);
-            let (c_out, d_in) = bounded(1);
-            let (d_out, e_in) = bounded(1);
-            let (e_out, a_in) = bounded(1);
-
-            let mut e_out_end = 0isize;
-
-            let output = &mut (e_out, &mut e_out_end);
-
-            let mut a = IntcodeMachine::new(&code, once(a).chain(once(0).chain(a_in)), a_out);
-            let mut b = IntcodeMachine::new(&code, once(b).chain(b_in), b_out);
-            let mut c = IntcodeMachine::new(&code, once(c).chain(c_in), c_out);
-            let mut d = IntcodeMachine::new(&code, once(d).chain(d_in), d_out);
-            let mut e = IntcodeMachine::new(&code, once(e).chain(e_in), output);
-
-            let mut pool = scoped_threadpool::Pool::new(5);
-
-            pool.scoped(|scope| {
-                scope.execute(|| a.run());
-                scope.execute(|| b.run());
-                scope.execute(|| c.run());
-                scope.execute(|| d.run());
-                scope.execute(|| e.run());
-            });
-
-            e_out_end
-        })
-        .max()
-        .unwrap()
+    max_signal(&parse_input(input), Topology::ring(5), 5..=9)
 }
 
 #[test]
@@ -16,7 +16,7 @@ fn part1(input: &str) -> isize {
     let mut output = 0isize;
     let mut machine = IntcodeMachine::new(&input, once(1), &mut output);
 
-    machine.run();
+    machine.run().unwrap();
 
     output
 }
@@ -25,9 +25,10 @@ fn part1(input: &str) -> isize {
 fn part2(input: &str) -> isize {
     let input = parse_input(input);
     let mut output = 0isize;
-    let mut machine = IntcodeMachine::new(&input, once(2), &mut output);
+    let mut machine =
+        IntcodeMachine::new(&input, once(2), &mut output).with_engine(Engine::Threaded);
 
-    machine.run();
+    machine.run().unwrap();
 
     output
 }
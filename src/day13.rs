@@ -19,7 +19,7 @@ fn part1(input: &str) -> usize {
     let cpu_thread = std::thread::spawn(move || {
         let mut machine = IntcodeMachine::new(&program, empty(), intcode_input);
 
-        machine.run();
+        machine.run().unwrap();
     });
 
     let drawer_thread = std::thread::spawn(move || {
@@ -49,7 +49,7 @@ fn part2(input: &str) -> isize {
         program[0] = 2;
         let mut machine = IntcodeMachine::new(&program, intcode_input.into_iter(), intcode_tx);
 
-        machine.run();
+        machine.run().unwrap();
     });
 
     let drawer_thread = std::thread::spawn(move || {
@@ -177,7 +177,7 @@ fn part2(input: &str) -> isize {
             intcode_tx,
         );
 
-        machine.run();
+        machine.run().unwrap();
     });
 
     let drawer_thread = std::thread::spawn(move || -> Result<isize, ()> {
@@ -0,0 +1,60 @@
+use advent_of_code_2019::intcode::{asm, debugger::Debugger};
+use std::io::{stdin, stdout, BufRead, Write};
+
+fn main() {
+    let path = match std::env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("usage: debugger <program or assembly source>");
+            std::process::exit(1);
+        }
+    };
+
+    let text = std::fs::read_to_string(&path).unwrap_or_else(|e| {
+        eprintln!("couldn't read {}: {}", path, e);
+        std::process::exit(1);
+    });
+
+    // Accept either a comma separated image or assembler source.
+    let program = text
+        .trim()
+        .split(',')
+        .map(|n| n.trim().parse())
+        .collect::<Result<Vec<isize>, _>>()
+        .or_else(|_| asm::assemble(&text))
+        .unwrap_or_else(|e| {
+            eprintln!("{}: {}", path, e);
+            std::process::exit(1);
+        });
+
+    let mut debugger = Debugger::new(&program);
+    let mut last = String::from("list");
+
+    print!("{}", debugger.execute("list 0 5"));
+
+    let stdin = stdin();
+    let mut stdin = stdin.lock();
+
+    loop {
+        print!("(icdb) ");
+        stdout().flush().unwrap();
+
+        let mut line = String::new();
+        if stdin.read_line(&mut line).unwrap_or(0) == 0 {
+            break;
+        }
+
+        // An empty line repeats the previous command, like gdb.
+        let line = if line.trim().is_empty() {
+            last.clone()
+        } else {
+            line
+        };
+        if matches!(line.trim(), "q" | "quit") {
+            break;
+        }
+
+        print!("{}", debugger.execute(&line));
+        last = line;
+    }
+}
@@ -1,48 +1,653 @@
-use enum_dispatch::enum_dispatch;
-
-macro_rules! debug {
-    ($($ts:tt)*) => {{
-        #[cfg(debug_assertions)]
-        eprintln!($($ts)*);
-    }}
-}
-
-pub fn empty() -> impl Iterator<Item = isize> {
-    None.into_iter()
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+pub mod asm;
+pub mod ascii;
+#[cfg(feature = "futures")]
+mod async_io;
+mod cache;
+pub mod cfg;
+pub mod custom;
+pub mod debugger;
+pub mod device;
+pub mod disasm;
+#[cfg(test)]
+mod fixture;
+pub mod host;
+mod memory;
+pub mod network;
+pub mod profile;
+mod snapshot;
+pub mod source;
+mod threaded;
+pub mod topology;
+pub mod trace;
+pub mod transpile;
+mod word;
+
+use cache::DecodeCache;
+use custom::{Custom, Extension, Machine, Opcodes};
+use device::{Device, Devices};
+use host::Host;
+pub use memory::Memory;
+pub use snapshot::{SnapshotError, SnapshotFormat};
+pub use source::{InputPolicy, OnClosed, OnWouldBlock, Received, Source};
+use threaded::CodeTable;
+pub use trace::{NoTracer, Tracer};
+pub use word::Word;
+
+pub fn empty() -> std::iter::Empty<isize> {
+    std::iter::empty()
 }
 
-pub struct IntcodeMachine<R: Iterator<Item = isize>, W: Sink<isize>> {
-    data: Vec<isize>,
+/// Runs an Intcode program over words of type `N`, `isize` unless the
+/// program needs more range.
+#[derive(Clone)]
+pub struct IntcodeMachine<R: Source<N>, W: Sink<N>, T: Tracer<N> = NoTracer, N: Word = isize> {
+    data: Memory<N>,
     ip: usize,
-    relative_base: isize,
+    relative_base: N,
     input: R,
     output: W,
+    pending: VecDeque<N>,
+    /// Outputs `run_until_event` hasn't reported yet, while it's running or
+    /// when one instruction made several.
+    outputs: Option<VecDeque<N>>,
+    writes: Option<Vec<(usize, N)>>,
     running: bool,
+    engine: Engine,
+    overflow: Overflow,
+    input_policy: InputPolicy<N>,
+    decoded: DecodeCache<N>,
+    threaded: CodeTable<R, W, T, N>,
+    opcodes: Opcodes<N>,
+    devices: Devices<N>,
+    tracer: T,
 }
 
-impl<R: Iterator<Item = isize>, W: Sink<isize>> IntcodeMachine<R, W> {
-    pub fn new(program: &[isize], input: R, output: W) -> Self {
-        let mut data = vec![0; 4096];
-        data[..program.len()].copy_from_slice(&program);
+impl<R: Source<N>, W: Sink<N>, N: Word> IntcodeMachine<R, W, NoTracer, N> {
+    pub fn new(program: &[N], input: R, output: W) -> Self {
+        Self::with_memory(Memory::new(program), input, output)
+    }
+
+    /// Creates a machine running from an already loaded `Memory`, e.g. one
+    /// that is sparse or has an upper bound.
+    pub fn with_memory(data: Memory<N>, input: R, output: W) -> Self {
+        Self {
+            data,
+            ip: 0,
+            relative_base: N::ZERO.clone(),
+            input,
+            output,
+            pending: VecDeque::new(),
+            outputs: None,
+            writes: None,
+            running: true,
+            engine: Engine::default(),
+            overflow: Overflow::default(),
+            input_policy: InputPolicy::default(),
+            decoded: DecodeCache::new(),
+            threaded: CodeTable::new(),
+            opcodes: Opcodes::new(),
+            devices: Devices::new(),
+            tracer: NoTracer,
+        }
+    }
+
+    /// Creates a machine that resumes from a previously captured state.
+    pub fn from_state(state: MachineState<N>, input: R, output: W) -> Self {
+        let MachineState { memory, ip, relative_base, halted, pending } = state;
+
+        Self {
+            ip,
+            relative_base,
+            running: !halted,
+            pending: pending.into(),
+            ..Self::with_memory(memory, input, output)
+        }
+    }
+}
+
+impl<R: Source<N>, W: Sink<N>, T: Tracer<N>, N: Word> IntcodeMachine<R, W, T, N> {
+    /// Replaces the machine's tracer, which is told about every instruction
+    /// decoded and every memory access, I/O and jump made from then on.
+    pub fn with_tracer<U: Tracer<N>>(self, tracer: U) -> IntcodeMachine<R, W, U, N> {
+        IntcodeMachine {
+            data: self.data,
+            ip: self.ip,
+            relative_base: self.relative_base,
+            input: self.input,
+            output: self.output,
+            pending: self.pending,
+            outputs: self.outputs,
+            writes: self.writes,
+            running: self.running,
+            engine: self.engine,
+            overflow: self.overflow,
+            input_policy: self.input_policy,
+            decoded: self.decoded,
+            // Threaded code calls handlers compiled for the old tracer type,
+            // so it's compiled again as the machine runs.
+            threaded: CodeTable::new(),
+            opcodes: self.opcodes,
+            devices: self.devices,
+            tracer,
+        }
+    }
+
+    pub fn tracer(&self) -> &T {
+        &self.tracer
+    }
+
+    pub fn tracer_mut(&mut self) -> &mut T {
+        &mut self.tracer
+    }
+
+    /// Captures memory, registers and inputs queued with `provide_input` so
+    /// the machine can be restored to this point later. The input `Source`
+    /// and output `Sink` aren't part of the state.
+    pub fn snapshot(&self) -> MachineState<N> {
+        MachineState {
+            memory: self.data.clone(),
+            ip: self.ip,
+            relative_base: self.relative_base.clone(),
+            halted: !self.running,
+            pending: self.pending.iter().cloned().collect(),
+        }
+    }
+
+    pub fn restore(&mut self, state: MachineState<N>) {
+        self.data = state.memory;
+        self.ip = state.ip;
+        self.relative_base = state.relative_base;
+        self.running = !state.halted;
+        self.pending = state.pending.into();
+        self.decoded.clear();
+        self.threaded.clear();
+    }
+
+    /// Selects how instructions are executed. All engines produce the same
+    /// results; they only differ in speed.
+    pub fn with_engine(mut self, engine: Engine) -> Self {
+        self.engine = engine;
+        self.decoded.clear();
+        self.threaded.clear();
+        self
+    }
+
+    pub fn engine(&self) -> Engine {
+        self.engine
+    }
+
+    /// Selects what happens when an addition or multiplication, including
+    /// the ones done for relative addressing, doesn't fit in an `N`.
+    pub fn with_overflow(mut self, overflow: Overflow) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    pub fn overflow(&self) -> Overflow {
+        self.overflow
+    }
+
+    /// Adds `I` as the instruction for `opcode`, replacing any custom
+    /// instruction already there. Panics if `opcode` is one of the standard
+    /// set's or doesn't fit in an opcode word's two digits, or if `I` takes
+    /// more than three parameters.
+    pub fn with_opcode<I: Custom<N>>(mut self, opcode: usize) -> Self {
+        check_opcode(opcode);
+        assert!(I::PARAMS <= 3, "{} takes more than 3 parameters", I::MNEMONIC);
+
+        self.opcodes.insert::<I>(opcode);
+        self.decoded.clear();
+        self.threaded.clear();
+        self
+    }
+
+    /// Maps `device` over the addresses from `start`, so reads and writes
+    /// there go to it instead of memory. Clones of the machine share it.
+    /// Panics if it overlaps a device that's already mapped.
+    pub fn with_device<D: Device<N> + 'static>(
+        mut self,
+        start: usize,
+        device: Arc<Mutex<D>>,
+    ) -> Self {
+        self.devices.insert(start, device);
+        self
+    }
 
-        Self { data, ip: 0, relative_base: 0, input, output, running: true }
+    /// Selects what input instructions do when the input `Source` has
+    /// nothing to give. By default they suspend the machine either way.
+    pub fn with_input_policy(mut self, policy: InputPolicy<N>) -> Self {
+        self.input_policy = policy;
+        self
     }
 
-    pub fn run(&mut self) {
+    pub fn input_policy(&self) -> &InputPolicy<N> {
+        &self.input_policy
+    }
+
+    /// Runs the program until it halts, or returns the first fault it hits.
+    /// On a fault `ip` is left pointing at the offending instruction.
+    pub fn run(&mut self) -> Result<ExitReason, IntcodeError> {
         while self.running {
-            let inst = Instructions::decode(
-                &self.data[self.ip..],
-                #[cfg(debug_assertions)]
-                self.ip,
-            );
-            self.ip += inst.size();
-            inst.execute(self);
+            self.execute_next()?;
+        }
+
+        Ok(ExitReason::Halted)
+    }
+
+    /// Like `run`, but gives up after executing `budget` instructions.
+    pub fn run_for(&mut self, budget: usize) -> Result<ExitReason, IntcodeError> {
+        for _ in 0..budget {
+            if !self.running {
+                return Ok(ExitReason::Halted);
+            }
+
+            self.execute_next()?;
         }
+
+        if self.running {
+            Ok(ExitReason::BudgetExhausted)
+        } else {
+            Ok(ExitReason::Halted)
+        }
+    }
+
+    /// Decodes and executes exactly one instruction, reporting what it did.
+    pub fn step(&mut self) -> Result<Step<N>, IntcodeError> {
+        let ip_before = self.ip;
+
+        if !self.running {
+            let opcode = self.data[ip_before].saturate();
+            return Err(IntcodeError::new(ip_before, opcode, ErrorKind::Halted));
+        }
+
+        let instruction = self.decode(ip_before)?;
+
+        self.writes = Some(Vec::new());
+        let result = self.execute_next();
+        let writes = self.writes.take().unwrap_or_default();
+        result?;
+
+        Ok(Step { instruction, ip_before, ip_after: self.ip, writes })
+    }
+
+    /// Runs the program until it produces an output, needs an input that
+    /// hasn't been provided, or halts. Outputs are still sent to the `Sink`.
+    ///
+    /// This lets a single thread drive several machines cooperatively: feed
+    /// values with `provide_input` whenever `Event::NeedsInput` comes back
+    /// and call this again to resume from the same instruction. An
+    /// instruction that outputs several values reports them one per call
+    /// before the next instruction runs.
+    pub fn run_until_event(&mut self) -> Result<Event<N>, IntcodeError> {
+        let mut outputs = self.outputs.take().unwrap_or_default();
+
+        let event = loop {
+            if let Some(value) = outputs.pop_front() {
+                break Ok(Event::Output(value));
+            }
+
+            if !self.running {
+                break Ok(Event::Halted);
+            }
+
+            self.outputs = Some(outputs);
+            let result = self.execute_next();
+            outputs = self.outputs.take().unwrap_or_default();
+
+            match result {
+                Ok(()) => {}
+                Err(IntcodeError { kind: ErrorKind::InputExhausted, .. }) => {
+                    break Ok(Event::NeedsInput)
+                }
+                Err(e) => break Err(e),
+            }
+        };
+
+        if !outputs.is_empty() {
+            self.outputs = Some(outputs);
+        }
+
+        event
     }
 
-    pub fn data(&self) -> &[isize] {
+    /// Queues a value to be read by the next input instruction, ahead of
+    /// anything waiting in the input `Source`.
+    pub fn provide_input(&mut self, value: N) {
+        self.pending.push_back(value);
+    }
+
+    pub fn is_halted(&self) -> bool {
+        !self.running
+    }
+
+    pub fn data(&self) -> &Memory<N> {
         &self.data
     }
+
+    /// Gives direct access to memory. Writes made through it can't be
+    /// tracked, so this drops all cached instructions.
+    pub fn data_mut(&mut self) -> &mut Memory<N> {
+        self.decoded.clear();
+        self.threaded.clear();
+        &mut self.data
+    }
+
+    pub fn output_mut(&mut self) -> &mut W {
+        &mut self.output
+    }
+
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+
+    pub fn set_ip(&mut self, ip: usize) {
+        self.ip = ip;
+    }
+
+    pub fn relative_base(&self) -> &N {
+        &self.relative_base
+    }
+
+    pub fn set_relative_base(&mut self, relative_base: N) {
+        self.relative_base = relative_base;
+    }
+
+    fn execute_next(&mut self) -> Result<(), IntcodeError> {
+        let ip = self.ip;
+
+        let result = match self.threaded.get(ip) {
+            Some(op) => {
+                self.tracer.decode(ip, &op.instruction);
+                let (handler, args) = (op.handler, op.args.clone());
+                self.ip += op.size;
+
+                handler(self, &args)
+            }
+            None => {
+                let inst = match self.decoded.get(ip) {
+                    Some(inst) => inst,
+                    None => {
+                        let inst = self.decode(ip)?;
+
+                        match self.engine {
+                            Engine::Interpreter => {}
+                            Engine::Cached => self.decoded.insert(ip, inst.clone()),
+                            Engine::Threaded => self.threaded.compile(ip, inst.clone()),
+                        }
+
+                        inst
+                    }
+                };
+                self.tracer.decode(ip, &inst);
+                self.ip += inst.size();
+
+                inst.execute(self)
+            }
+        };
+
+        result.map_err(|kind| {
+            self.ip = ip;
+            IntcodeError::new(ip, self.data[ip].saturate(), kind)
+        })
+    }
+
+    fn decode(&self, ip: usize) -> Result<Instructions<N>, IntcodeError> {
+        let ints = self.data.fetch(ip).map_err(|kind| IntcodeError::new(ip, 0, kind))?;
+
+        match self.opcodes.decode(&ints) {
+            Some(instruction) => Ok(instruction),
+            None => Instructions::decode(&ints, ip),
+        }
+    }
+
+    /// The address a relative mode parameter of `offset` refers to.
+    #[inline]
+    pub fn relative(&self, offset: &N) -> Result<usize, ErrorKind> {
+        address(self.add(&self.relative_base, offset)?)
+    }
+
+    #[inline]
+    fn add(&self, a: &N, b: &N) -> Result<N, ErrorKind> {
+        match self.overflow {
+            Overflow::Trap => a.checked_add(b).ok_or(ErrorKind::Overflow),
+            Overflow::Wrap => Ok(a.wrapping_add(b)),
+        }
+    }
+
+    #[inline]
+    fn mul(&self, a: &N, b: &N) -> Result<N, ErrorKind> {
+        match self.overflow {
+            Overflow::Trap => a.checked_mul(b).ok_or(ErrorKind::Overflow),
+            Overflow::Wrap => Ok(a.wrapping_mul(b)),
+        }
+    }
+
+    fn next_input(&mut self) -> Result<N, ErrorKind> {
+        if let Some(value) = self.pending.pop_front() {
+            return Ok(value);
+        }
+
+        loop {
+            return match self.input.recv() {
+                Received::Value(value) => Ok(value),
+                Received::WouldBlock => match &self.input_policy.would_block {
+                    OnWouldBlock::Suspend => Err(ErrorKind::InputExhausted),
+                    OnWouldBlock::Wait => {
+                        std::thread::yield_now();
+                        continue;
+                    }
+                    OnWouldBlock::Supply(value) => Ok(value.clone()),
+                },
+                Received::Closed => match &self.input_policy.closed {
+                    OnClosed::Suspend => Err(ErrorKind::InputExhausted),
+                    OnClosed::Supply(value) => Ok(value.clone()),
+                },
+            };
+        }
+    }
+
+    /// Reads the next input the way an input instruction does, for custom
+    /// instructions. `ErrorKind::InputExhausted` suspends the machine.
+    ///
+    /// A suspended instruction runs again from the start when the machine
+    /// resumes, so read every input before writing or outputting anything.
+    pub fn input(&mut self) -> Result<N, ErrorKind> {
+        let value = self.next_input()?;
+        self.tracer.input(value.clone());
+
+        Ok(value)
+    }
+
+    /// Sends `value` the way an output instruction does, for custom
+    /// instructions. `run_until_event` reports it as `Event::Output`.
+    pub fn output(&mut self, value: N) {
+        self.tracer.output(value.clone());
+
+        match &mut self.outputs {
+            Some(outputs) => {
+                self.output.send(value.clone());
+                outputs.push_back(value);
+            }
+            None => self.output.send(value),
+        }
+    }
+
+    /// Stops the machine as if it had run a halt instruction.
+    pub fn halt(&mut self) {
+        self.running = false;
+    }
+
+    /// Reads memory the way instructions do: devices are read, the tracer
+    /// is told and memory limits apply.
+    pub fn read(&mut self, address: usize) -> Result<N, ErrorKind> {
+        let value = match self.devices.get(address) {
+            Some((device, offset)) => device.lock().unwrap().read(offset),
+            None => self.data.read(address)?,
+        };
+        self.tracer.read(address, value.clone());
+
+        Ok(value)
+    }
+
+    /// Writes memory the way instructions do, so devices are written,
+    /// cached instructions that cover `address` are dropped and the tracer is
+    /// told.
+    pub fn write(&mut self, address: usize, value: N) -> Result<(), ErrorKind> {
+        match self.devices.get(address) {
+            Some((device, offset)) => device.lock().unwrap().write(offset, value.clone()),
+            None => {
+                self.data.write(address, value.clone())?;
+                self.decoded.invalidate(address);
+                self.threaded.invalidate(address);
+            }
+        }
+
+        if let Some(writes) = &mut self.writes {
+            writes.push((address, value.clone()));
+        }
+
+        self.tracer.write(address, value);
+
+        Ok(())
+    }
+}
+
+impl<R: Source, W: Sink<isize>, T: Tracer> IntcodeMachine<R, W, T> {
+    /// Adds the `host` instruction as `opcode`, running the callbacks in
+    /// `host`. Clones of the machine share them. Panics like `with_opcode`.
+    pub fn with_host(mut self, opcode: usize, host: Host) -> Self {
+        check_opcode(opcode);
+
+        self.opcodes.insert_handler(opcode, "host", 3, host::handler(host));
+        self.decoded.clear();
+        self.threaded.clear();
+        self
+    }
+}
+
+/// How an `IntcodeMachine` executes instructions.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Engine {
+    /// Decodes every instruction each time it's executed.
+    Interpreter,
+    /// Decodes each address once and reuses the result until the code there
+    /// is overwritten.
+    #[default]
+    Cached,
+    /// Translates each address into a handler specialised for its operand
+    /// modes, falling back to the interpreter for code that modifies itself.
+    Threaded,
+}
+
+/// What an `IntcodeMachine` does when arithmetic overflows.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Overflow {
+    /// Faults with `ErrorKind::Overflow`.
+    #[default]
+    Trap,
+    /// Wraps around in two's complement.
+    Wrap,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExitReason {
+    Halted,
+    BudgetExhausted,
+}
+
+/// A point-in-time copy of an `IntcodeMachine`'s memory and registers.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MachineState<N = isize> {
+    pub memory: Memory<N>,
+    pub ip: usize,
+    pub relative_base: N,
+    pub halted: bool,
+    /// Inputs queued with `IntcodeMachine::provide_input` but not yet read.
+    pub pending: Vec<N>,
+}
+
+/// The result of executing a single instruction with `IntcodeMachine::step`.
+#[derive(Clone, Debug)]
+pub struct Step<N = isize> {
+    pub instruction: Instructions<N>,
+    pub ip_before: usize,
+    pub ip_after: usize,
+    /// Memory cells written by the instruction, as `(address, value)`.
+    pub writes: Vec<(usize, N)>,
+}
+
+/// Why `IntcodeMachine::run_until_event` returned control to the caller.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event<N = isize> {
+    NeedsInput,
+    Output(N),
+    Halted,
+}
+
+/// A fault raised while decoding or executing an instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IntcodeError {
+    /// Address of the faulting instruction.
+    pub ip: usize,
+    /// Raw opcode word found at `ip`, including parameter modes. Words that
+    /// don't fit in an `isize` are clamped to its range.
+    pub opcode: isize,
+    pub kind: ErrorKind,
+}
+
+impl IntcodeError {
+    pub fn new(ip: usize, opcode: isize, kind: ErrorKind) -> Self {
+        Self { ip, opcode, kind }
+    }
+}
+
+impl fmt::Display for IntcodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (ip: {}, opcode: {})", self.kind, self.ip, self.opcode)
+    }
+}
+
+impl std::error::Error for IntcodeError {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    InvalidOpcode(isize),
+    InvalidMode(usize),
+    ImmediateDestination,
+    AddressOutOfBounds(isize),
+    TruncatedInstruction,
+    InputExhausted,
+    /// A host instruction asked for a call its machine's `Host` doesn't
+    /// have.
+    UnknownHostCall(isize),
+    /// An arithmetic result didn't fit in a word, with `Overflow::Trap`.
+    Overflow,
+    /// An async output sink was closed while the machine was running.
+    OutputClosed,
+    Halted,
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorKind::InvalidOpcode(n) => write!(f, "invalid opcode {}", n),
+            ErrorKind::InvalidMode(n) => write!(f, "invalid parameter mode {}", n),
+            ErrorKind::ImmediateDestination => write!(f, "destination can't be immediate mode"),
+            ErrorKind::AddressOutOfBounds(n) => write!(f, "address {} is out of bounds", n),
+            ErrorKind::TruncatedInstruction => write!(f, "instruction runs past end of memory"),
+            ErrorKind::InputExhausted => write!(f, "input exhausted"),
+            ErrorKind::UnknownHostCall(n) => write!(f, "no host call {}", n),
+            ErrorKind::Overflow => write!(f, "arithmetic overflow"),
+            ErrorKind::OutputClosed => write!(f, "output closed"),
+            ErrorKind::Halted => write!(f, "machine has halted"),
+        }
+    }
 }
 
 pub trait Sink<Item> {
@@ -70,8 +675,8 @@ impl Sink<isize> for std::io::Stdout {
     }
 }
 
-impl Sink<isize> for () {
-    fn send(&mut self, _: isize) {}
+impl<T> Sink<T> for () {
+    fn send(&mut self, _: T) {}
 }
 
 impl Sink<isize> for [isize; 1] {
@@ -92,6 +697,12 @@ impl<T> Sink<T> for Vec<T> {
     }
 }
 
+impl<T, U: Sink<T> + ?Sized> Sink<T> for Box<U> {
+    fn send(&mut self, item: T) {
+        (**self).send(item);
+    }
+}
+
 impl<T, U: Sink<T>> Sink<T> for &'_ mut U {
     fn send(&mut self, item: T) {
         (*self).send(item);
@@ -99,92 +710,124 @@ impl<T, U: Sink<T>> Sink<T> for &'_ mut U {
 }
 
 #[derive(Clone, Copy, Debug)]
-pub enum Operand {
-    Immediate(isize),
+pub enum Operand<N = isize> {
+    Immediate(N),
     Position(usize),
-    Relative(isize),
+    Relative(N),
 }
 
-impl Operand {
-    fn from_parts(mode: Mode, value: isize) -> Self {
-        match mode {
+impl<N: Word> Operand<N> {
+    fn from_parts(mode: Mode, value: N) -> Result<Self, ErrorKind> {
+        Ok(match mode {
             Mode::Immediate => Operand::Immediate(value),
-            Mode::Position => Operand::Position(value as usize),
+            Mode::Position => Operand::Position(address(value)?),
             Mode::Relative => Operand::Relative(value),
+        })
+    }
+
+    #[inline]
+    pub fn resolve<M: Machine<N> + ?Sized>(&self, machine: &mut M) -> Result<N, ErrorKind> {
+        match self {
+            Operand::Immediate(n) => Ok(n.clone()),
+            Operand::Position(p) => machine.read(*p),
+            Operand::Relative(r) => machine.read(machine.relative(r)?),
         }
     }
+}
 
-    pub fn resolve<R: Iterator<Item = isize>, W: Sink<isize>>(
-        self,
-        machine: &IntcodeMachine<R, W>,
-    ) -> isize {
+impl<N: fmt::Display> fmt::Display for Operand<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Operand::Immediate(n) => n,
-            Operand::Position(p) => machine.data[p],
-            Operand::Relative(r) => machine.data[(machine.relative_base + r) as usize],
+            Operand::Immediate(n) => write!(f, "imm({})", n),
+            Operand::Position(p) => write!(f, "pos({})", p),
+            Operand::Relative(r) => write!(f, "rel({})", r),
         }
     }
 }
 
 #[derive(Clone, Copy, Debug)]
-pub enum Destination {
+pub enum Destination<N = isize> {
     Position(usize),
-    Relative(usize),
+    Relative(N),
 }
 
-impl Destination {
-    fn from_parts(mode: Mode, value: isize) -> Self {
+impl<N: Word> Destination<N> {
+    fn from_parts(mode: Mode, value: N) -> Result<Self, ErrorKind> {
         match mode {
-            Mode::Immediate => panic!("Destinations can't be immediate mode"),
-            Mode::Position => Destination::Position(value as usize),
-            Mode::Relative => Destination::Relative(value as usize),
+            Mode::Immediate => Err(ErrorKind::ImmediateDestination),
+            Mode::Position => Ok(Destination::Position(address(value)?)),
+            Mode::Relative => Ok(Destination::Relative(value)),
         }
     }
 
-    pub fn resolve<R: Iterator<Item = isize>, W: Sink<isize>>(
-        self,
-        machine: &IntcodeMachine<R, W>,
-    ) -> usize {
+    #[inline]
+    pub fn resolve<M: Machine<N> + ?Sized>(&self, machine: &M) -> Result<usize, ErrorKind> {
         match self {
-            Destination::Position(n) => n,
-            Destination::Relative(r) => (r as isize + machine.relative_base) as usize,
+            Destination::Position(n) => Ok(*n),
+            Destination::Relative(r) => machine.relative(r),
         }
     }
 }
 
-#[derive(Debug)]
-pub struct Add {
-    dst: Destination,
-    op1: Operand,
-    op2: Operand,
+impl<N: fmt::Display> fmt::Display for Destination<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Destination::Position(p) => write!(f, "pos({})", p),
+            Destination::Relative(r) => write!(f, "rel({})", r),
+        }
+    }
+}
+
+/// Panics unless `opcode` is free for a custom instruction.
+fn check_opcode(opcode: usize) {
+    assert!(
+        opcode < 100 && !matches!(opcode, ADD_OP..=MRB_OP | HALT_OP),
+        "opcode {} can't be redefined",
+        opcode
+    );
+}
+
+#[inline]
+fn address<N: Word>(value: N) -> Result<usize, ErrorKind> {
+    match value.to_isize() {
+        Some(n) => usize::try_from(n).map_err(|_| ErrorKind::AddressOutOfBounds(n)),
+        None => Err(ErrorKind::AddressOutOfBounds(value.saturate())),
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Add<N = isize> {
+    dst: Destination<N>,
+    op1: Operand<N>,
+    op2: Operand<N>,
 }
 
-impl Add {
-    pub fn new(dst: Destination, op1: Operand, op2: Operand) -> Self {
+impl<N: Word> Add<N> {
+    pub fn new(dst: Destination<N>, op1: Operand<N>, op2: Operand<N>) -> Self {
         Self { dst, op1, op2 }
     }
 
-    fn decode(opcode: Opcode, ints: &[isize]) -> Instructions {
-        let op1 = Operand::from_parts(opcode.param1, ints[0]);
-        let op2 = Operand::from_parts(opcode.param2, ints[1]);
-        let dst = Destination::from_parts(opcode.param3, ints[2]);
+    fn decode(opcode: Opcode, ints: &[N]) -> Result<Instructions<N>, ErrorKind> {
+        let op1 = Operand::from_parts(opcode.param1, ints[0].clone())?;
+        let op2 = Operand::from_parts(opcode.param2, ints[1].clone())?;
+        let dst = Destination::from_parts(opcode.param3, ints[2].clone())?;
 
-        Self::new(dst, op1, op2).into()
+        Ok(Self::new(dst, op1, op2).into())
     }
 }
 
-impl Instruction for Add {
-    fn execute<R: Iterator<Item = isize>, W: Sink<isize>>(
+impl<N: Word> Instruction<N> for Add<N> {
+    fn execute<R: Source<N>, W: Sink<N>, T: Tracer<N>>(
         &self,
-        machine: &mut IntcodeMachine<R, W>,
-    ) {
-        let op1 = self.op1.resolve(&machine);
-        let op2 = self.op2.resolve(&machine);
-        let dst = self.dst.resolve(&machine);
+        machine: &mut IntcodeMachine<R, W, T, N>,
+    ) -> Result<(), ErrorKind> {
+        let op1 = self.op1.resolve(machine)?;
+        let op2 = self.op2.resolve(machine)?;
+        let dst = self.dst.resolve(machine)?;
 
-        debug!("Add: memory[{}] = {} + {}", dst, op1, op2);
+        let value = machine.add(&op1, &op2)?;
 
-        machine.data[dst] = op1 + op2;
+        machine.write(dst, value)
     }
 
     fn size(&self) -> usize {
@@ -192,39 +835,39 @@ impl Instruction for Add {
     }
 }
 
-#[derive(Debug)]
-pub struct Mul {
-    dst: Destination,
-    op1: Operand,
-    op2: Operand,
+#[derive(Clone, Copy, Debug)]
+pub struct Mul<N = isize> {
+    dst: Destination<N>,
+    op1: Operand<N>,
+    op2: Operand<N>,
 }
 
-impl Mul {
-    pub fn new(dst: Destination, op1: Operand, op2: Operand) -> Self {
+impl<N: Word> Mul<N> {
+    pub fn new(dst: Destination<N>, op1: Operand<N>, op2: Operand<N>) -> Self {
         Self { dst, op1, op2 }
     }
 
-    fn decode(opcode: Opcode, ints: &[isize]) -> Instructions {
-        let op1 = Operand::from_parts(opcode.param1, ints[0]);
-        let op2 = Operand::from_parts(opcode.param2, ints[1]);
-        let dst = Destination::from_parts(opcode.param3, ints[2]);
+    fn decode(opcode: Opcode, ints: &[N]) -> Result<Instructions<N>, ErrorKind> {
+        let op1 = Operand::from_parts(opcode.param1, ints[0].clone())?;
+        let op2 = Operand::from_parts(opcode.param2, ints[1].clone())?;
+        let dst = Destination::from_parts(opcode.param3, ints[2].clone())?;
 
-        Self::new(dst, op1, op2).into()
+        Ok(Self::new(dst, op1, op2).into())
     }
 }
 
-impl Instruction for Mul {
-    fn execute<R: Iterator<Item = isize>, W: Sink<isize>>(
+impl<N: Word> Instruction<N> for Mul<N> {
+    fn execute<R: Source<N>, W: Sink<N>, T: Tracer<N>>(
         &self,
-        machine: &mut IntcodeMachine<R, W>,
-    ) {
-        let op1 = self.op1.resolve(&machine);
-        let op2 = self.op2.resolve(&machine);
-        let dst = self.dst.resolve(&machine);
+        machine: &mut IntcodeMachine<R, W, T, N>,
+    ) -> Result<(), ErrorKind> {
+        let op1 = self.op1.resolve(machine)?;
+        let op2 = self.op2.resolve(machine)?;
+        let dst = self.dst.resolve(machine)?;
 
-        debug!("Mul: memory[{}] = {} * {}", dst, op1, op2);
+        let value = machine.mul(&op1, &op2)?;
 
-        machine.data[dst] = op1 * op2;
+        machine.write(dst, value)
     }
 
     fn size(&self) -> usize {
@@ -232,7 +875,7 @@ impl Instruction for Mul {
     }
 }
 
-#[derive(Debug, Default)]
+#[derive(Clone, Copy, Debug, Default)]
 pub struct Halt;
 
 impl Halt {
@@ -241,12 +884,14 @@ impl Halt {
     }
 }
 
-impl Instruction for Halt {
-    fn execute<R: Iterator<Item = isize>, W: Sink<isize>>(
+impl<N: Word> Instruction<N> for Halt {
+    fn execute<R: Source<N>, W: Sink<N>, T: Tracer<N>>(
         &self,
-        machine: &mut IntcodeMachine<R, W>,
-    ) {
-        machine.running = false;
+        machine: &mut IntcodeMachine<R, W, T, N>,
+    ) -> Result<(), ErrorKind> {
+        machine.halt();
+
+        Ok(())
     }
 
     fn size(&self) -> usize {
@@ -254,34 +899,32 @@ impl Instruction for Halt {
     }
 }
 
-#[derive(Debug)]
-pub struct Input {
-    operand: Destination,
+#[derive(Clone, Copy, Debug)]
+pub struct Input<N = isize> {
+    operand: Destination<N>,
 }
 
-impl Input {
-    pub fn new(operand: Destination) -> Self {
+impl<N: Word> Input<N> {
+    pub fn new(operand: Destination<N>) -> Self {
         Self { operand }
     }
 
-    fn decode(opcode: Opcode, ints: &[isize]) -> Instructions {
-        let operand = Destination::from_parts(opcode.param1, ints[0]);
+    fn decode(opcode: Opcode, ints: &[N]) -> Result<Instructions<N>, ErrorKind> {
+        let operand = Destination::from_parts(opcode.param1, ints[0].clone())?;
 
-        Self::new(operand).into()
+        Ok(Self::new(operand).into())
     }
 }
 
-impl Instruction for Input {
-    fn execute<R: Iterator<Item = isize>, W: Sink<isize>>(
+impl<N: Word> Instruction<N> for Input<N> {
+    fn execute<R: Source<N>, W: Sink<N>, T: Tracer<N>>(
         &self,
-        machine: &mut IntcodeMachine<R, W>,
-    ) {
-        let inp = machine.input.next().unwrap();
-        let dst = self.operand.resolve(&machine);
-
-        debug!("Input: memory[{}] = {}", dst, inp);
+        machine: &mut IntcodeMachine<R, W, T, N>,
+    ) -> Result<(), ErrorKind> {
+        let dst = self.operand.resolve(machine)?;
+        let inp = machine.input()?;
 
-        machine.data[dst] = inp;
+        machine.write(dst, inp)
     }
 
     fn size(&self) -> usize {
@@ -289,33 +932,33 @@ impl Instruction for Input {
     }
 }
 
-#[derive(Debug)]
-pub struct Output {
-    operand: Operand,
+#[derive(Clone, Copy, Debug)]
+pub struct Output<N = isize> {
+    operand: Operand<N>,
 }
 
-impl Output {
-    pub fn new(operand: Operand) -> Self {
+impl<N: Word> Output<N> {
+    pub fn new(operand: Operand<N>) -> Self {
         Self { operand }
     }
 
-    fn decode(opcode: Opcode, ints: &[isize]) -> Instructions {
-        let operand = Operand::from_parts(opcode.param1, ints[0]);
+    fn decode(opcode: Opcode, ints: &[N]) -> Result<Instructions<N>, ErrorKind> {
+        let operand = Operand::from_parts(opcode.param1, ints[0].clone())?;
 
-        Self::new(operand).into()
+        Ok(Self::new(operand).into())
     }
 }
 
-impl Instruction for Output {
-    fn execute<R: Iterator<Item = isize>, W: Sink<isize>>(
+impl<N: Word> Instruction<N> for Output<N> {
+    fn execute<R: Source<N>, W: Sink<N>, T: Tracer<N>>(
         &self,
-        machine: &mut IntcodeMachine<R, W>,
-    ) {
-        let val = self.operand.resolve(&machine);
+        machine: &mut IntcodeMachine<R, W, T, N>,
+    ) -> Result<(), ErrorKind> {
+        let val = self.operand.resolve(machine)?;
 
-        debug!("Output: sending {}", val);
+        machine.output(val);
 
-        machine.output.send(val);
+        Ok(())
     }
 
     fn size(&self) -> usize {
@@ -323,35 +966,37 @@ impl Instruction for Output {
     }
 }
 
-#[derive(Debug)]
-pub struct JumpIfTrue {
-    test: Operand,
-    jump_to: Operand,
+#[derive(Clone, Copy, Debug)]
+pub struct JumpIfTrue<N = isize> {
+    test: Operand<N>,
+    jump_to: Operand<N>,
 }
 
-impl JumpIfTrue {
-    pub fn new(test: Operand, jump_to: Operand) -> Self {
+impl<N: Word> JumpIfTrue<N> {
+    pub fn new(test: Operand<N>, jump_to: Operand<N>) -> Self {
         Self { test, jump_to }
     }
 
-    fn decode(opcode: Opcode, ints: &[isize]) -> Instructions {
-        let test = Operand::from_parts(opcode.param1, ints[0]);
-        let jump_to = Operand::from_parts(opcode.param2, ints[1]);
+    fn decode(opcode: Opcode, ints: &[N]) -> Result<Instructions<N>, ErrorKind> {
+        let test = Operand::from_parts(opcode.param1, ints[0].clone())?;
+        let jump_to = Operand::from_parts(opcode.param2, ints[1].clone())?;
 
-        Self::new(test, jump_to).into()
+        Ok(Self::new(test, jump_to).into())
     }
 }
 
-impl Instruction for JumpIfTrue {
-    fn execute<R: Iterator<Item = isize>, W: Sink<isize>>(
+impl<N: Word> Instruction<N> for JumpIfTrue<N> {
+    fn execute<R: Source<N>, W: Sink<N>, T: Tracer<N>>(
         &self,
-        machine: &mut IntcodeMachine<R, W>,
-    ) {
-        if self.test.resolve(&machine) != 0 {
-            let jump_to = self.jump_to.resolve(&machine) as usize;
-            debug!("JumpIfTrue: ip = {}", jump_to);
+        machine: &mut IntcodeMachine<R, W, T, N>,
+    ) -> Result<(), ErrorKind> {
+        if !self.test.resolve(machine)?.is_zero() {
+            let jump_to = address(self.jump_to.resolve(machine)?)?;
+            machine.tracer.jump(machine.ip - self.size(), jump_to);
             machine.ip = jump_to;
         }
+
+        Ok(())
     }
 
     fn size(&self) -> usize {
@@ -359,35 +1004,37 @@ impl Instruction for JumpIfTrue {
     }
 }
 
-#[derive(Debug)]
-pub struct JumpIfFalse {
-    test: Operand,
-    jump_to: Operand,
+#[derive(Clone, Copy, Debug)]
+pub struct JumpIfFalse<N = isize> {
+    test: Operand<N>,
+    jump_to: Operand<N>,
 }
 
-impl JumpIfFalse {
-    pub fn new(test: Operand, jump_to: Operand) -> Self {
+impl<N: Word> JumpIfFalse<N> {
+    pub fn new(test: Operand<N>, jump_to: Operand<N>) -> Self {
         Self { test, jump_to }
     }
 
-    fn decode(opcode: Opcode, ints: &[isize]) -> Instructions {
-        let test = Operand::from_parts(opcode.param1, ints[0]);
-        let jump_to = Operand::from_parts(opcode.param2, ints[1]);
+    fn decode(opcode: Opcode, ints: &[N]) -> Result<Instructions<N>, ErrorKind> {
+        let test = Operand::from_parts(opcode.param1, ints[0].clone())?;
+        let jump_to = Operand::from_parts(opcode.param2, ints[1].clone())?;
 
-        Self::new(test, jump_to).into()
+        Ok(Self::new(test, jump_to).into())
     }
 }
 
-impl Instruction for JumpIfFalse {
-    fn execute<R: Iterator<Item = isize>, W: Sink<isize>>(
+impl<N: Word> Instruction<N> for JumpIfFalse<N> {
+    fn execute<R: Source<N>, W: Sink<N>, T: Tracer<N>>(
         &self,
-        machine: &mut IntcodeMachine<R, W>,
-    ) {
-        if self.test.resolve(&machine) == 0 {
-            let jump_to = self.jump_to.resolve(&machine) as usize;
-            debug!("JumpIfTrue: ip = {}", jump_to);
+        machine: &mut IntcodeMachine<R, W, T, N>,
+    ) -> Result<(), ErrorKind> {
+        if self.test.resolve(machine)?.is_zero() {
+            let jump_to = address(self.jump_to.resolve(machine)?)?;
+            machine.tracer.jump(machine.ip - self.size(), jump_to);
             machine.ip = jump_to;
         }
+
+        Ok(())
     }
 
     fn size(&self) -> usize {
@@ -395,42 +1042,40 @@ impl Instruction for JumpIfFalse {
     }
 }
 
-#[derive(Debug)]
-pub struct LessThan {
-    dst: Destination,
-    op1: Operand,
-    op2: Operand,
+#[derive(Clone, Copy, Debug)]
+pub struct LessThan<N = isize> {
+    dst: Destination<N>,
+    op1: Operand<N>,
+    op2: Operand<N>,
 }
 
-impl LessThan {
-    pub fn new(dst: Destination, op1: Operand, op2: Operand) -> Self {
+impl<N: Word> LessThan<N> {
+    pub fn new(dst: Destination<N>, op1: Operand<N>, op2: Operand<N>) -> Self {
         Self { dst, op1, op2 }
     }
 
-    fn decode(opcode: Opcode, ints: &[isize]) -> Instructions {
-        let op1 = Operand::from_parts(opcode.param1, ints[0]);
-        let op2 = Operand::from_parts(opcode.param2, ints[1]);
-        let dst = Destination::from_parts(opcode.param3, ints[2]);
+    fn decode(opcode: Opcode, ints: &[N]) -> Result<Instructions<N>, ErrorKind> {
+        let op1 = Operand::from_parts(opcode.param1, ints[0].clone())?;
+        let op2 = Operand::from_parts(opcode.param2, ints[1].clone())?;
+        let dst = Destination::from_parts(opcode.param3, ints[2].clone())?;
 
-        Self::new(dst, op1, op2).into()
+        Ok(Self::new(dst, op1, op2).into())
     }
 }
 
-impl Instruction for LessThan {
-    fn execute<R: Iterator<Item = isize>, W: Sink<isize>>(
+impl<N: Word> Instruction<N> for LessThan<N> {
+    fn execute<R: Source<N>, W: Sink<N>, T: Tracer<N>>(
         &self,
-        machine: &mut IntcodeMachine<R, W>,
-    ) {
-        let dst = self.dst.resolve(&machine);
-        let op1 = self.op1.resolve(&machine);
-        let op2 = self.op2.resolve(&machine);
+        machine: &mut IntcodeMachine<R, W, T, N>,
+    ) -> Result<(), ErrorKind> {
+        let dst = self.dst.resolve(machine)?;
+        let op1 = self.op1.resolve(machine)?;
+        let op2 = self.op2.resolve(machine)?;
 
         if op1 < op2 {
-            debug!("LessThan: memory[{}] = 1 ({} < {})", dst, op1, op2);
-            machine.data[dst] = 1;
+            machine.write(dst, N::from_isize(1))
         } else {
-            debug!("LessThan: memory[{}] = 0 ({} >= {})", dst, op1, op2);
-            machine.data[dst] = 0;
+            machine.write(dst, N::from_isize(0))
         }
     }
 
@@ -439,42 +1084,40 @@ impl Instruction for LessThan {
     }
 }
 
-#[derive(Debug)]
-pub struct EqualTo {
-    dst: Destination,
-    op1: Operand,
-    op2: Operand,
+#[derive(Clone, Copy, Debug)]
+pub struct EqualTo<N = isize> {
+    dst: Destination<N>,
+    op1: Operand<N>,
+    op2: Operand<N>,
 }
 
-impl EqualTo {
-    pub fn new(dst: Destination, op1: Operand, op2: Operand) -> Self {
+impl<N: Word> EqualTo<N> {
+    pub fn new(dst: Destination<N>, op1: Operand<N>, op2: Operand<N>) -> Self {
         Self { dst, op1, op2 }
     }
 
-    fn decode(opcode: Opcode, ints: &[isize]) -> Instructions {
-        let op1 = Operand::from_parts(opcode.param1, ints[0]);
-        let op2 = Operand::from_parts(opcode.param2, ints[1]);
-        let dst = Destination::from_parts(opcode.param3, ints[2]);
+    fn decode(opcode: Opcode, ints: &[N]) -> Result<Instructions<N>, ErrorKind> {
+        let op1 = Operand::from_parts(opcode.param1, ints[0].clone())?;
+        let op2 = Operand::from_parts(opcode.param2, ints[1].clone())?;
+        let dst = Destination::from_parts(opcode.param3, ints[2].clone())?;
 
-        Self::new(dst, op1, op2).into()
+        Ok(Self::new(dst, op1, op2).into())
     }
 }
 
-impl Instruction for EqualTo {
-    fn execute<R: Iterator<Item = isize>, W: Sink<isize>>(
+impl<N: Word> Instruction<N> for EqualTo<N> {
+    fn execute<R: Source<N>, W: Sink<N>, T: Tracer<N>>(
         &self,
-        machine: &mut IntcodeMachine<R, W>,
-    ) {
-        let dst = self.dst.resolve(&machine);
-        let op1 = self.op1.resolve(&machine);
-        let op2 = self.op2.resolve(&machine);
+        machine: &mut IntcodeMachine<R, W, T, N>,
+    ) -> Result<(), ErrorKind> {
+        let dst = self.dst.resolve(machine)?;
+        let op1 = self.op1.resolve(machine)?;
+        let op2 = self.op2.resolve(machine)?;
 
         if op1 == op2 {
-            debug!("EqualTo: memory[{}] = 1 ({} == {})", dst, op1, op2);
-            machine.data[dst] = 1;
+            machine.write(dst, N::from_isize(1))
         } else {
-            debug!("EqualTo: memory[{}] = 0 ({} != {})", dst, op1, op2);
-            machine.data[dst] = 0;
+            machine.write(dst, N::from_isize(0))
         }
     }
 
@@ -483,33 +1126,34 @@ impl Instruction for EqualTo {
     }
 }
 
-#[derive(Debug)]
-pub struct ModRelBase {
-    operand: Operand,
+#[derive(Clone, Copy, Debug)]
+pub struct ModRelBase<N = isize> {
+    operand: Operand<N>,
 }
 
-impl ModRelBase {
-    pub fn new(operand: Operand) -> Self {
+impl<N: Word> ModRelBase<N> {
+    pub fn new(operand: Operand<N>) -> Self {
         Self { operand }
     }
 
-    fn decode(opcode: Opcode, ints: &[isize]) -> Instructions {
-        let operand = Operand::from_parts(opcode.param1, ints[0]);
+    fn decode(opcode: Opcode, ints: &[N]) -> Result<Instructions<N>, ErrorKind> {
+        let operand = Operand::from_parts(opcode.param1, ints[0].clone())?;
 
-        Self::new(operand).into()
+        Ok(Self::new(operand).into())
     }
 }
 
-impl Instruction for ModRelBase {
-    fn execute<R: Iterator<Item = isize>, W: Sink<isize>>(
+impl<N: Word> Instruction<N> for ModRelBase<N> {
+    fn execute<R: Source<N>, W: Sink<N>, T: Tracer<N>>(
         &self,
-        machine: &mut IntcodeMachine<R, W>,
-    ) {
-        let value = self.operand.resolve(&machine);
+        machine: &mut IntcodeMachine<R, W, T, N>,
+    ) -> Result<(), ErrorKind> {
+        let value = self.operand.resolve(machine)?;
 
-        debug!("ModRelBase: relative_base = {} (value: {})", machine.relative_base + value, value);
+        machine.relative_base = machine.add(&machine.relative_base, &value)?;
+        machine.tracer.relative_base(machine.relative_base.clone());
 
-        machine.relative_base += value;
+        Ok(())
     }
 
     fn size(&self) -> usize {
@@ -517,47 +1161,130 @@ impl Instruction for ModRelBase {
     }
 }
 
-#[enum_dispatch(Instructions)]
-pub trait Instruction {
-    fn execute<R: Iterator<Item = isize>, W: Sink<isize>>(
+pub trait Instruction<N: Word = isize> {
+    fn execute<R: Source<N>, W: Sink<N>, T: Tracer<N>>(
         &self,
-        machine: &mut IntcodeMachine<R, W>,
-    );
+        machine: &mut IntcodeMachine<R, W, T, N>,
+    ) -> Result<(), ErrorKind>;
 
     fn size(&self) -> usize;
 }
 
-#[enum_dispatch]
-#[derive(Debug)]
-pub enum Instructions {
-    Add,
-    Mul,
-    Input,
-    Output,
-    JumpIfTrue,
-    JumpIfFalse,
-    LessThan,
-    EqualTo,
-    ModRelBase,
-    Halt,
-}
-
-impl Instructions {
-    pub fn decode(ints: &[isize], #[cfg(debug_assertions)] ip: usize) -> Self {
-        const ADD_OP: usize = 1;
-        const MUL_OP: usize = 2;
-        const INP_OP: usize = 3;
-        const OUT_OP: usize = 4;
-        const JIT_OP: usize = 5;
-        const JIF_OP: usize = 6;
-        const LST_OP: usize = 7;
-        const EQU_OP: usize = 8;
-        const MRB_OP: usize = 9;
-        const HALT_OP: usize = 99;
-
-        let opcode = Opcode::from(ints[0]);
-
-        let bytes = &ints[1..];
+#[derive(Clone, Copy, Debug)]
+pub enum Instructions<N = isize> {
+    Add(Add<N>),
+    Mul(Mul<N>),
+    Input(Input<N>),
+    Output(Output<N>),
+    JumpIfTrue(JumpIfTrue<N>),
+    JumpIfFalse(JumpIfFalse<N>),
+    LessThan(LessThan<N>),
+    EqualTo(EqualTo<N>),
+    ModRelBase(ModRelBase<N>),
+    Halt(Halt),
+    Extension(Extension<N>),
+}
+
+/// Forwards to the instruction in each variant, and wraps each instruction
+/// type into its variant with `From`.
+macro_rules! dispatch {
+    ($($variant:ident($inner:ty)),*) => {
+        impl<N: Word> Instruction<N> for Instructions<N> {
+            #[inline]
+            fn execute<R: Source<N>, W: Sink<N>, T: Tracer<N>>(
+                &self,
+                machine: &mut IntcodeMachine<R, W, T, N>,
+            ) -> Result<(), ErrorKind> {
+                match self {
+                    $(Instructions::$variant(i) => i.execute(machine),)*
+                }
+            }
+
+            #[inline]
+            fn size(&self) -> usize {
+                match self {
+                    $(Instructions::$variant(i) => Instruction::<N>::size(i),)*
+                }
+            }
+        }
+
+        $(impl<N> From<$inner> for Instructions<N> {
+            fn from(instruction: $inner) -> Self {
+                Instructions::$variant(instruction)
+            }
+        })*
+    };
+}
+
+dispatch!(
+    Add(Add<N>),
+    Mul(Mul<N>),
+    Input(Input<N>),
+    Output(Output<N>),
+    JumpIfTrue(JumpIfTrue<N>),
+    JumpIfFalse(JumpIfFalse<N>),
+    LessThan(LessThan<N>),
+    EqualTo(EqualTo<N>),
+    ModRelBase(ModRelBase<N>),
+    Halt(Halt),
+    Extension(Extension<N>)
+);
+
+const ADD_OP: usize = 1;
+const MUL_OP: usize = 2;
+const INP_OP: usize = 3;
+const OUT_OP: usize = 4;
+const JIT_OP: usize = 5;
+const JIF_OP: usize = 6;
+const LST_OP: usize = 7;
+const EQU_OP: usize = 8;
+const MRB_OP: usize = 9;
+const HALT_OP: usize = 99;
+
+impl<N> Instructions<N> {
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Instructions::Add(_) => "add",
+            Instructions::Mul(_) => "mul",
+            Instructions::Input(_) => "input",
+            Instructions::Output(_) => "output",
+            Instructions::JumpIfTrue(_) => "jit",
+            Instructions::JumpIfFalse(_) => "jif",
+            Instructions::LessThan(_) => "lt",
+            Instructions::EqualTo(_) => "eq",
+            Instructions::ModRelBase(_) => "arel",
+            Instructions::Halt(_) => "halt",
+            Instructions::Extension(e) => e.mnemonic(),
+        }
+    }
+}
+
+impl<N: Word> Instructions<N> {
+    /// Decodes the instruction at the start of `ints`, which was read from
+    /// address `ip`.
+    pub fn decode(ints: &[N], ip: usize) -> Result<Self, IntcodeError> {
+        let word = ints.first().map_or(0, Word::saturate);
+        let error = |kind| IntcodeError::new(ip, word, kind);
+
+        if ints.is_empty() {
+            return Err(error(ErrorKind::TruncatedInstruction));
+        }
+
+        let opcode = ints[0]
+            .to_isize()
+            .ok_or(ErrorKind::InvalidOpcode(word))
+            .and_then(Opcode::try_from)
+            .map_err(error)?;
+
+        let params = match opcode.opcode {
+            ADD_OP | MUL_OP | LST_OP | EQU_OP => 3,
+            JIT_OP | JIF_OP => 2,
+            INP_OP | OUT_OP | MRB_OP => 1,
+            HALT_OP => 0,
+            _ => return Err(error(ErrorKind::InvalidOpcode(word))),
+        };
+
+        let bytes = ints.get(1..=params).ok_or_else(|| error(ErrorKind::TruncatedInstruction))?;
 
         match opcode.opcode {
             ADD_OP => Add::decode(opcode, bytes),
@@ -569,30 +1296,54 @@ impl Instructions {
             LST_OP => LessThan::decode(opcode, bytes),
             EQU_OP => EqualTo::decode(opcode, bytes),
             MRB_OP => ModRelBase::decode(opcode, bytes),
-            HALT_OP => Halt::new().into(),
-            #[cfg(debug_assertions)]
-            #[cold]
-            n => panic!("Invalid opcode: {}, ip: {}", n, ip),
-            #[cfg(not(debug_assertions))]
-            #[cold]
-            n => panic!("Invalid opcode: {}", n),
+            _ => Ok(Halt::new().into()),
+        }
+        .map_err(error)
+    }
+}
+
+impl<N: fmt::Display> fmt::Display for Instructions<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = self.mnemonic();
+
+        match self {
+            Instructions::Add(Add { dst, op1, op2 })
+            | Instructions::Mul(Mul { dst, op1, op2 })
+            | Instructions::LessThan(LessThan { dst, op1, op2 })
+            | Instructions::EqualTo(EqualTo { dst, op1, op2 }) => {
+                write!(f, "{} {}, {}, {}", name, op1, op2, dst)
+            }
+            Instructions::JumpIfTrue(JumpIfTrue { test, jump_to })
+            | Instructions::JumpIfFalse(JumpIfFalse { test, jump_to }) => {
+                write!(f, "{} {}, {}", name, test, jump_to)
+            }
+            Instructions::Input(Input { operand }) => write!(f, "{} {}", name, operand),
+            Instructions::Output(Output { operand })
+            | Instructions::ModRelBase(ModRelBase { operand }) => {
+                write!(f, "{} {}", name, operand)
+            }
+            Instructions::Halt(_) => write!(f, "{}", name),
+            Instructions::Extension(e) => write!(f, "{}", e),
         }
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 enum Mode {
     Position = 0,
     Immediate = 1,
     Relative = 2,
 }
 
-impl From<usize> for Mode {
-    fn from(i: usize) -> Self {
+impl TryFrom<usize> for Mode {
+    type Error = ErrorKind;
+
+    fn try_from(i: usize) -> Result<Self, ErrorKind> {
         match i {
-            0 => Mode::Position,
-            1 => Mode::Immediate,
-            2 => Mode::Relative,
-            _ => panic!("Invalid mode"),
+            0 => Ok(Mode::Position),
+            1 => Ok(Mode::Immediate),
+            2 => Ok(Mode::Relative),
+            n => Err(ErrorKind::InvalidMode(n)),
         }
     }
 }
@@ -604,21 +1355,23 @@ struct Opcode {
     param3: Mode,
 }
 
-impl From<isize> for Opcode {
-    fn from(i: isize) -> Self {
-        let mut i = i as usize;
+impl TryFrom<isize> for Opcode {
+    type Error = ErrorKind;
+
+    fn try_from(word: isize) -> Result<Self, ErrorKind> {
+        let mut i = usize::try_from(word).map_err(|_| ErrorKind::InvalidOpcode(word))?;
         let opcode = i % 100;
 
         i /= 100;
-        let param1 = (i % 10).into();
+        let param1 = Mode::try_from(i % 10)?;
 
         i /= 10;
-        let param2 = (i % 10).into();
+        let param2 = Mode::try_from(i % 10)?;
 
         i /= 10;
-        let param3 = (i % 10).into();
+        let param3 = Mode::try_from(i % 10)?;
 
-        Self { opcode, param1, param2, param3 }
+        Ok(Self { opcode, param1, param2, param3 })
     }
 }
 
@@ -637,17 +1390,17 @@ mod tests {
         let input = parse_input(input);
         let mut output = 0isize;
         let mut machine = IntcodeMachine::new(&input, once(7), &mut output);
-        machine.run();
+        machine.run().unwrap();
         assert_eq!(output, 999);
 
         let mut output = 0isize;
         let mut machine = IntcodeMachine::new(&input, once(8), &mut output);
-        machine.run();
+        machine.run().unwrap();
         assert_eq!(output, 1000);
 
         let mut output = 0isize;
         let mut machine = IntcodeMachine::new(&input, once(9), &mut output);
-        machine.run();
+        machine.run().unwrap();
         assert_eq!(output, 1001);
     }
 
@@ -657,21 +1410,205 @@ mod tests {
         let inp = parse_input(quine);
         let mut output = Vec::new();
         let mut machine = IntcodeMachine::new(&inp, None.into_iter(), &mut output);
-        machine.run();
+        machine.run().unwrap();
         assert_eq!(inp, output);
 
         let input = "104,1125899906842624,99";
         let input = parse_input(input);
         let mut output = 0isize;
         let mut machine = IntcodeMachine::new(&input, None.into_iter(), &mut output);
-        machine.run();
+        machine.run().unwrap();
         assert_eq!(output, 1_125_899_906_842_624);
 
         let input = "1102,34915192,34915192,7,4,7,99,0";
         let input = parse_input(input);
         let mut output = 0isize;
         let mut machine = IntcodeMachine::new(&input, None.into_iter(), &mut output);
-        machine.run();
+        machine.run().unwrap();
         assert_eq!(output.to_string().len(), 16);
     }
+
+    #[test]
+    fn cooperative_feedback_loop() {
+        let input =
+            "3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5";
+        let input = parse_input(input);
+        let mut amps: Vec<_> = [9, 8, 7, 6, 5]
+            .iter()
+            .map(|&phase| {
+                let mut machine = IntcodeMachine::new(&input, empty(), ());
+                machine.provide_input(phase);
+                machine
+            })
+            .collect();
+
+        let mut signal = 0;
+        'feedback: loop {
+            for amp in &mut amps {
+                amp.provide_input(signal);
+
+                match amp.run_until_event().unwrap() {
+                    Event::Output(value) => signal = value,
+                    Event::Halted => break 'feedback,
+                    Event::NeedsInput => unreachable!(),
+                }
+            }
+        }
+
+        assert_eq!(signal, 139_629_729);
+        assert!(amps[0].is_halted());
+    }
+
+    #[test]
+    fn single_step() {
+        let input = parse_input("1101,2,3,7,1105,1,0,0");
+        let mut machine = IntcodeMachine::new(&input, empty(), ());
+
+        let step = machine.step().unwrap();
+        assert!(matches!(step.instruction, Instructions::Add(_)));
+        assert_eq!((step.ip_before, step.ip_after), (0, 4));
+        assert_eq!(step.writes, vec![(7, 5)]);
+
+        let step = machine.step().unwrap();
+        assert!(matches!(step.instruction, Instructions::JumpIfTrue(_)));
+        assert_eq!((step.ip_before, step.ip_after), (4, 0));
+        assert!(step.writes.is_empty());
+
+        assert_eq!(machine.run_for(100), Ok(ExitReason::BudgetExhausted));
+        assert_eq!(machine.ip(), 0);
+
+        let input = parse_input("1101,2,3,7,99");
+        let mut machine = IntcodeMachine::new(&input, empty(), ());
+        assert_eq!(machine.run_for(2), Ok(ExitReason::Halted));
+        assert_eq!(machine.step().unwrap_err().kind, ErrorKind::Halted);
+    }
+
+    #[test]
+    fn fork_from_snapshot() {
+        let input = parse_input("3,12,3,13,2,12,13,14,4,14,99");
+        let mut machine = IntcodeMachine::new(&input, empty(), ());
+        machine.provide_input(6);
+        assert_eq!(machine.run_until_event(), Ok(Event::NeedsInput));
+
+        let state = machine.snapshot();
+        let results: Vec<_> = (1..=3)
+            .map(|n| {
+                let mut fork = machine.clone();
+                fork.provide_input(n);
+                fork.run_until_event().unwrap()
+            })
+            .collect();
+        assert_eq!(results, vec![Event::Output(6), Event::Output(12), Event::Output(18)]);
+
+        machine.provide_input(7);
+        machine.run().unwrap();
+        machine.restore(state.clone());
+        machine.provide_input(10);
+        assert_eq!(machine.run_until_event(), Ok(Event::Output(60)));
+
+        let mut resumed = IntcodeMachine::from_state(state, once(5), ());
+        assert_eq!(resumed.run_until_event(), Ok(Event::Output(30)));
+    }
+
+    #[test]
+    fn faults() {
+        let fault = |program: &str, input: Vec<isize>| {
+            let program = parse_input(program);
+            IntcodeMachine::new(&program, input.into_iter(), ()).run().unwrap_err()
+        };
+
+        assert_eq!(
+            fault("1101,1,1,5,42", vec![]),
+            IntcodeError::new(4, 42, ErrorKind::InvalidOpcode(42))
+        );
+        assert_eq!(
+            fault("1301,1,1,5,99", vec![]),
+            IntcodeError::new(0, 1301, ErrorKind::InvalidMode(3))
+        );
+        assert_eq!(
+            fault("11101,1,1,5,99", vec![]),
+            IntcodeError::new(0, 11101, ErrorKind::ImmediateDestination)
+        );
+        assert_eq!(
+            fault("109,-5,204,0,99", vec![]),
+            IntcodeError::new(2, 204, ErrorKind::AddressOutOfBounds(-5))
+        );
+        assert_eq!(
+            fault("3,5,3,5,99", vec![1]),
+            IntcodeError::new(2, 3, ErrorKind::InputExhausted)
+        );
+
+        let program = parse_input("1101,1,1,100,99");
+        let memory = Memory::new(&program).with_limit(64);
+        let mut machine = IntcodeMachine::with_memory(memory, empty(), ());
+        assert_eq!(
+            machine.run(),
+            Err(IntcodeError::new(0, 1101, ErrorKind::AddressOutOfBounds(100)))
+        );
+    }
+
+    #[test]
+    fn overflow() {
+        let max = isize::MAX.to_string();
+        let programs =
+            [format!("1101,{},1,7,4,7,99,0", max), format!("109,{},22201,1,1,0,99", max)];
+
+        for program in &programs {
+            let program = parse_input(program);
+            let (result, _) =
+                fixture::run_on_every_engine(|| IntcodeMachine::new(&program, empty(), Vec::new()));
+            assert_eq!(result.unwrap_err().kind, ErrorKind::Overflow);
+        }
+
+        let program = parse_input(&programs[0]);
+        let mut machine =
+            IntcodeMachine::new(&program, empty(), Vec::new()).with_overflow(Overflow::Wrap);
+        machine.run().unwrap();
+        assert_eq!(machine.output_mut(), &[isize::MIN]);
+    }
+
+    #[test]
+    fn self_modifying_code() {
+        // Each pass increments the immediate operand of the output instruction.
+        let program = parse_input("104,1,1001,1,1,1,1105,1,0");
+
+        for &engine in &fixture::ENGINES {
+            let mut machine =
+                IntcodeMachine::new(&program, empty(), Vec::new()).with_engine(engine);
+            assert_eq!(machine.run_for(9), Ok(ExitReason::BudgetExhausted));
+            assert_eq!(machine.output_mut(), &vec![1, 2, 3]);
+
+            machine.data_mut().write(0, 99).unwrap();
+            assert_eq!(machine.run_for(1), Ok(ExitReason::Halted));
+        }
+    }
+
+    #[test]
+    fn engines_agree() {
+        let programs = [
+            ("109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99", vec![]),
+            ("3,21,1008,21,8,20,1005,20,22,107,8,21,20,1006,20,31,1106,0,36,98,0,0,1002,21,125,20,4,20,1105,1,46,104,999,1105,1,46,1101,1000,1,20,4,20,1105,1,46,98,99", vec![7]),
+            ("3,9,8,9,10,9,4,9,99,-1,8", vec![8]),
+            ("1,9,10,3,2,3,11,0,99,30,40,50", vec![]),
+        ];
+
+        for (program, input) in programs.iter() {
+            let program = parse_input(program);
+            let run = |engine| {
+                let mut tracer = trace::TextTracer::new(Vec::new());
+                let mut machine =
+                    IntcodeMachine::new(&program, input.clone().into_iter(), Vec::new())
+                        .with_engine(engine)
+                        .with_tracer(&mut tracer);
+                let result = machine.run();
+                let output = std::mem::take(machine.output_mut());
+
+                (result, output, tracer.into_inner().unwrap())
+            };
+
+            let expected = run(Engine::Interpreter);
+            assert_eq!(run(Engine::Cached), expected);
+            assert_eq!(run(Engine::Threaded), expected);
+        }
+    }
 }
@@ -0,0 +1,159 @@
+//! Adapters for programs that talk in ASCII: commands go in as character
+//! codes ending in a newline, and text comes out the same way, sometimes
+//! followed by a value outside the ASCII range holding the actual answer.
+
+use super::Sink;
+use std::collections::VecDeque;
+
+/// Converts an output value to a character if it's in the ASCII range.
+pub fn to_char(value: isize) -> Option<char> {
+    if (0..128).contains(&value) {
+        Some(value as u8 as char)
+    } else {
+        None
+    }
+}
+
+/// Feeds lines of text in as character codes, ending each with a newline.
+/// Lines are only taken from the underlying iterator once the previous one
+/// has been read, so it can wait on the user to type them.
+#[derive(Clone, Debug)]
+pub struct AsciiSource<L> {
+    lines: L,
+    pending: VecDeque<isize>,
+}
+
+impl<L: Iterator<Item = String>> AsciiSource<L> {
+    pub fn new(lines: L) -> Self {
+        Self { lines, pending: VecDeque::new() }
+    }
+}
+
+impl AsciiSource<std::vec::IntoIter<String>> {
+    /// Feeds each line of `text` in turn.
+    pub fn from_text(text: &str) -> Self {
+        Self::new(text.lines().map(String::from).collect::<Vec<_>>().into_iter())
+    }
+}
+
+impl<L: Iterator<Item = String>> Iterator for AsciiSource<L> {
+    type Item = isize;
+
+    fn next(&mut self) -> Option<isize> {
+        if self.pending.is_empty() {
+            let line = self.lines.next()?;
+            self.pending.extend(line.bytes().map(isize::from));
+            self.pending.push_back(isize::from(b'\n'));
+        }
+
+        self.pending.pop_front()
+    }
+}
+
+/// Collects output into lines of text, keeping values outside the ASCII
+/// range separately.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AsciiSink {
+    lines: Vec<String>,
+    partial: String,
+    values: Vec<isize>,
+}
+
+impl AsciiSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Every complete line received so far, without its newline.
+    pub fn lines(&self) -> &[String] {
+        &self.lines
+    }
+
+    /// Text received since the last newline, e.g. a prompt.
+    pub fn partial(&self) -> &str {
+        &self.partial
+    }
+
+    /// Output values outside the ASCII range, in the order received.
+    pub fn values(&self) -> &[isize] {
+        &self.values
+    }
+
+    /// Removes and returns the complete lines received so far.
+    pub fn take_lines(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.lines)
+    }
+
+    /// All the text received, including any partial line.
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+
+        for line in &self.lines {
+            text.push_str(line);
+            text.push('\n');
+        }
+        text.push_str(&self.partial);
+
+        text
+    }
+}
+
+impl Sink<isize> for AsciiSink {
+    fn send(&mut self, item: isize) {
+        match to_char(item) {
+            Some('\n') => self.lines.push(std::mem::take(&mut self.partial)),
+            Some(c) => self.partial.push(c),
+            None => self.values.push(item),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::{asm, IntcodeMachine};
+
+    #[test]
+    fn source_ends_lines() {
+        let codes: Vec<_> = AsciiSource::from_text("NOT A J\nWALK").collect();
+        assert_eq!(codes, "NOT A J\nWALK\n".bytes().map(isize::from).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn sink_separates_values() {
+        let mut sink = AsciiSink::new();
+        for value in "Hull damage:\n".bytes().map(isize::from).chain(vec![19_358_416, 62, 32]) {
+            sink.send(value);
+        }
+
+        assert_eq!(sink.lines(), &["Hull damage:"]);
+        assert_eq!(sink.partial(), "> ");
+        assert_eq!(sink.values(), &[19_358_416]);
+        assert_eq!(sink.text(), "Hull damage:\n> ");
+    }
+
+    #[test]
+    fn echo_line() {
+        // Echoes input until a newline, then outputs a non-ASCII value.
+        let program = asm::assemble(
+            "
+loop:   input pos(c)
+        output pos(c)
+        eq pos(c), imm(10), pos(t)
+        jif pos(t), imm(loop)
+        output imm(1000)
+        halt
+c:      .data 0
+t:      .data 0
+",
+        )
+        .unwrap();
+
+        let mut machine =
+            IntcodeMachine::new(&program, AsciiSource::from_text("hello"), AsciiSink::new());
+        machine.run().unwrap();
+
+        assert_eq!(machine.output_mut().lines(), &["hello"]);
+        assert_eq!(machine.output_mut().values(), &[1000]);
+    }
+}
@@ -0,0 +1,415 @@
+//! A small assembler for Intcode.
+//!
+//! Each line holds an optional label, then an instruction or directive, then
+//! an optional `;` comment:
+//!
+//! ```text
+//!         jit pos(0), imm(start)   ; mnemonics match the disassembler
+//! .org 512
+//! start:  input rel(0)
+//!         arel imm(1)
+//!         jit imm(1), imm(start)
+//! table:  .data 1, 2, table+2
+//!         .fill 16, 0
+//! ```
+//!
+//! Operands are written `pos(x)`, `imm(x)` or `rel(x)`, where `x` is a number
+//! or a label plus or minus numbers and other labels. Directives:
+//!
+//! - `.org address` continues assembling at `address`, zero filling the gap.
+//! - `.data a, b, ...` emits the listed words.
+//! - `.fill count, value` emits `value` `count` times.
+//!
+//! A number followed by `:` (as printed by the disassembler) acts like
+//! `.org`, so listings can be assembled again.
+
+use super::{
+    Mode, ADD_OP, EQU_OP, HALT_OP, INP_OP, JIF_OP, JIT_OP, LST_OP, MRB_OP, MUL_OP, OUT_OP,
+};
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Param {
+    Operand,
+    Destination,
+}
+
+use Param::{Destination as D, Operand as O};
+
+const INSTRUCTIONS: &[(&str, usize, &[Param])] = &[
+    ("add", ADD_OP, &[O, O, D]),
+    ("mul", MUL_OP, &[O, O, D]),
+    ("input", INP_OP, &[D]),
+    ("output", OUT_OP, &[O]),
+    ("jit", JIT_OP, &[O, O]),
+    ("jif", JIF_OP, &[O, O]),
+    ("lt", LST_OP, &[O, O, D]),
+    ("eq", EQU_OP, &[O, O, D]),
+    ("arel", MRB_OP, &[O]),
+    ("halt", HALT_OP, &[]),
+];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AsmError {
+    /// 1-based source line the error was found on.
+    pub line: usize,
+    pub message: String,
+}
+
+impl AsmError {
+    fn new(line: usize, message: impl Into<String>) -> Self {
+        Self { line, message: message.into() }
+    }
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// Assembles `source` into a program image that `IntcodeMachine::new` can run.
+pub fn assemble(source: &str) -> Result<Vec<isize>, AsmError> {
+    let mut labels = HashMap::new();
+    let mut statements = Vec::new();
+    let mut address = 0;
+
+    // First pass: parse every line and assign addresses to labels.
+    for (i, line) in source.lines().enumerate() {
+        let line_no = i + 1;
+        let mut rest = line.split(';').next().unwrap_or("").trim();
+
+        while let Some((label, after)) = split_label(rest) {
+            if label.chars().all(|c| c.is_ascii_digit()) {
+                let org = label.parse().map_err(|_| AsmError::new(line_no, "address too large"))?;
+                statements.push((line_no, Statement::Org(Expr::number(org))));
+                address = org;
+            } else if labels.insert(label.to_string(), address as isize).is_some() {
+                return Err(AsmError::new(line_no, format!("label `{}` defined twice", label)));
+            }
+
+            rest = after;
+        }
+
+        if rest.is_empty() {
+            continue;
+        }
+
+        let error = |message| AsmError::new(line_no, message);
+        let statement = Statement::parse(rest).map_err(error)?;
+        address = match &statement {
+            Statement::Org(expr) => expr.eval(&labels).and_then(checked_address).map_err(error)?,
+            Statement::Fill(count, _) => {
+                address + count.eval(&labels).and_then(checked_address).map_err(error)?
+            }
+            statement => address + statement.size(),
+        };
+
+        statements.push((line_no, statement));
+    }
+
+    // Second pass: resolve operands and emit words.
+    let mut program = Vec::new();
+    for (line_no, statement) in statements {
+        statement.emit(&labels, &mut program).map_err(|message| AsmError::new(line_no, message))?;
+    }
+
+    Ok(program)
+}
+
+enum Statement {
+    Instruction { opcode: usize, operands: Vec<(Param, Mode, Expr)> },
+    Org(Expr),
+    Data(Vec<Expr>),
+    Fill(Expr, Expr),
+}
+
+impl Statement {
+    fn parse(text: &str) -> Result<Self, String> {
+        let (name, args) = match text.find(char::is_whitespace) {
+            Some(i) => (&text[..i], text[i..].trim()),
+            None => (text, ""),
+        };
+        let args: Vec<&str> =
+            if args.is_empty() { Vec::new() } else { args.split(',').map(str::trim).collect() };
+
+        let expect_args = |n: usize| {
+            if args.len() == n {
+                Ok(())
+            } else {
+                Err(format!("`{}` takes {} argument(s), found {}", name, n, args.len()))
+            }
+        };
+
+        match name {
+            ".org" => {
+                expect_args(1)?;
+                Ok(Statement::Org(Expr::parse(args[0])?))
+            }
+            ".data" => {
+                Ok(Statement::Data(args.iter().map(|a| Expr::parse(a)).collect::<Result<_, _>>()?))
+            }
+            ".fill" => {
+                expect_args(2)?;
+                Ok(Statement::Fill(Expr::parse(args[0])?, Expr::parse(args[1])?))
+            }
+            _ => {
+                let &(_, opcode, params) = INSTRUCTIONS
+                    .iter()
+                    .find(|(mnemonic, _, _)| *mnemonic == name)
+                    .ok_or_else(|| format!("unknown instruction `{}`", name))?;
+                expect_args(params.len())?;
+
+                let operands = params
+                    .iter()
+                    .zip(&args)
+                    .map(|(&param, arg)| {
+                        let (mode, expr) = parse_operand(arg)?;
+                        if param == Param::Destination && mode == Mode::Immediate {
+                            return Err(format!("destination `{}` can't be immediate mode", arg));
+                        }
+
+                        Ok((param, mode, expr))
+                    })
+                    .collect::<Result<_, String>>()?;
+
+                Ok(Statement::Instruction { opcode, operands })
+            }
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            Statement::Instruction { operands, .. } => operands.len() + 1,
+            Statement::Data(words) => words.len(),
+            Statement::Org(_) | Statement::Fill(..) => 0,
+        }
+    }
+
+    fn emit(
+        &self,
+        labels: &HashMap<String, isize>,
+        program: &mut Vec<isize>,
+    ) -> Result<(), String> {
+        match self {
+            Statement::Instruction { opcode, operands } => {
+                let modes = operands
+                    .iter()
+                    .rev()
+                    .fold(0, |modes, (_, mode, _)| modes * 10 + *mode as isize);
+                program.push(*opcode as isize + modes * 100);
+
+                for (_, mode, expr) in operands {
+                    let value = expr.eval(labels)?;
+                    if *mode == Mode::Position && value < 0 {
+                        return Err(format!("position operand {} is negative", value));
+                    }
+
+                    program.push(value);
+                }
+            }
+            Statement::Org(expr) => {
+                let org = checked_address(expr.eval(labels)?)?;
+                if org < program.len() {
+                    return Err(format!(
+                        ".org {} would overwrite code already placed up to {}",
+                        org,
+                        program.len()
+                    ));
+                }
+
+                program.resize(org, 0);
+            }
+            Statement::Data(words) => {
+                for expr in words {
+                    program.push(expr.eval(labels)?);
+                }
+            }
+            Statement::Fill(count, value) => {
+                let count = checked_address(count.eval(labels)?)?;
+                let value = value.eval(labels)?;
+                program.resize(program.len() + count, value);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A sum of numbers and labels, e.g. `table+2` or `end-start`.
+struct Expr {
+    terms: Vec<(bool, Term)>,
+}
+
+enum Term {
+    Number(isize),
+    Label(String),
+}
+
+impl Expr {
+    fn number(n: usize) -> Self {
+        Self { terms: vec![(false, Term::Number(n as isize))] }
+    }
+
+    fn parse(text: &str) -> Result<Self, String> {
+        let mut terms = Vec::new();
+        let mut negative = false;
+        let mut rest = text.trim();
+
+        loop {
+            match rest.chars().next() {
+                Some('-') => {
+                    negative = !negative;
+                    rest = rest[1..].trim_start();
+                    continue;
+                }
+                Some('+') => {
+                    rest = rest[1..].trim_start();
+                    continue;
+                }
+                _ => {}
+            }
+
+            let end = rest
+                .find(|c: char| c == '+' || c == '-' || c.is_whitespace())
+                .unwrap_or(rest.len());
+            let (atom, after) = rest.split_at(end);
+
+            let term = if atom.chars().all(|c| c.is_ascii_digit()) && !atom.is_empty() {
+                Term::Number(atom.parse().map_err(|_| format!("number `{}` is too large", atom))?)
+            } else if is_identifier(atom) {
+                Term::Label(atom.to_string())
+            } else {
+                return Err(format!("expected a number or label, found `{}`", text.trim()));
+            };
+
+            terms.push((negative, term));
+            negative = false;
+            rest = after.trim_start();
+
+            match rest.chars().next() {
+                None => return Ok(Self { terms }),
+                Some('+') | Some('-') => {}
+                Some(_) => return Err(format!("unexpected `{}` in `{}`", rest, text.trim())),
+            }
+        }
+    }
+
+    fn eval(&self, labels: &HashMap<String, isize>) -> Result<isize, String> {
+        self.terms.iter().try_fold(0isize, |sum, (negative, term)| {
+            let value = match term {
+                Term::Number(n) => *n,
+                Term::Label(label) => {
+                    *labels.get(label).ok_or_else(|| format!("undefined label `{}`", label))?
+                }
+            };
+
+            let sum = if *negative { sum.checked_sub(value) } else { sum.checked_add(value) };
+            sum.ok_or_else(|| String::from("expression overflows"))
+        })
+    }
+}
+
+/// Splits a leading `label:` off a line.
+fn split_label(text: &str) -> Option<(&str, &str)> {
+    let colon = text.find(':')?;
+    let label = text[..colon].trim();
+
+    if is_identifier(label) || (!label.is_empty() && label.chars().all(|c| c.is_ascii_digit())) {
+        Some((label, text[colon + 1..].trim()))
+    } else {
+        None
+    }
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+fn parse_operand(text: &str) -> Result<(Mode, Expr), String> {
+    let open = text.find('(').filter(|_| text.ends_with(')'));
+    let open = open
+        .ok_or_else(|| format!("expected `pos(..)`, `imm(..)` or `rel(..)`, found `{}`", text))?;
+
+    let mode = match text[..open].trim() {
+        "pos" => Mode::Position,
+        "imm" => Mode::Immediate,
+        "rel" => Mode::Relative,
+        mode => return Err(format!("unknown operand mode `{}`", mode)),
+    };
+
+    Ok((mode, Expr::parse(&text[open + 1..text.len() - 1])?))
+}
+
+fn checked_address(value: isize) -> Result<usize, String> {
+    if value < 0 {
+        Err(format!("address {} is negative", value))
+    } else {
+        Ok(value as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::{disasm, empty, IntcodeMachine};
+
+    #[test]
+    fn labels_and_directives() {
+        let source = "
+                    jit imm(1), imm(start)  ; skip over the table
+            table:  .data 10, 20, table+1
+            start:  add pos(table), pos(table + 1), pos(out)
+                    output pos(out)
+                    halt
+            out:    .fill 2, -1
+            .org 20
+                    .data 7
+        ";
+
+        let program = assemble(source).unwrap();
+        assert_eq!(
+            program,
+            vec![1105, 1, 6, 10, 20, 4, 1, 3, 4, 13, 4, 13, 99, -1, -1, 0, 0, 0, 0, 0, 7]
+        );
+
+        let mut output = Vec::new();
+        IntcodeMachine::new(&program, empty(), &mut output).run().unwrap();
+        assert_eq!(output, vec![30]);
+    }
+
+    #[test]
+    fn reassemble_listing() {
+        let program =
+            vec![1101, 5, -3, 20, 204, -1, 1, 1, 2, 99, 0, 12345, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!(assemble(&disasm::listing(&program)).unwrap(), program);
+    }
+
+    #[test]
+    fn errors() {
+        let err = |source| assemble(source).unwrap_err().to_string();
+
+        assert_eq!(err("halt\nfoo imm(1)"), "line 2: unknown instruction `foo`");
+        assert_eq!(
+            err("add imm(1), imm(2), imm(3)"),
+            "line 1: destination `imm(3)` can't be immediate mode"
+        );
+        assert_eq!(err("\n\njit imm(1), imm(nowhere)"), "line 3: undefined label `nowhere`");
+        assert_eq!(err("a: halt\na: halt"), "line 2: label `a` defined twice");
+        assert_eq!(
+            err("output 5"),
+            "line 1: expected `pos(..)`, `imm(..)` or `rel(..)`, found `5`"
+        );
+        assert_eq!(err("add imm(1), imm(2)"), "line 1: `add` takes 3 argument(s), found 2");
+    }
+}
@@ -0,0 +1,141 @@
+//! Running machines on an async executor, so a large network of them can
+//! share a few threads instead of needing one each.
+
+use super::{ErrorKind, Event, ExitReason, IntcodeError, IntcodeMachine, Sink, Source, Tracer};
+use futures::{SinkExt, Stream, StreamExt};
+
+impl<R: Source, W: Sink<isize>, T: Tracer> IntcodeMachine<R, W, T> {
+    /// Runs the program until it halts, awaiting the next value from `input`
+    /// whenever an input instruction finds nothing queued with
+    /// `provide_input` or waiting in the input `Source`, and sending every
+    /// output to `output` as well as to the machine's own `Sink`.
+    ///
+    /// `input` ending is reported as `ErrorKind::InputExhausted` and
+    /// `output` failing as `ErrorKind::OutputClosed`. `output` is dropped
+    /// when this returns, which closes it for whoever is reading it.
+    pub async fn run_async<S, K>(
+        &mut self,
+        mut input: S,
+        mut output: K,
+    ) -> Result<ExitReason, IntcodeError>
+    where
+        S: Stream<Item = isize> + Unpin,
+        K: futures::Sink<isize> + Unpin,
+    {
+        loop {
+            match self.run_until_event()? {
+                Event::Output(value) => {
+                    if output.send(value).await.is_err() {
+                        return Err(self.error(ErrorKind::OutputClosed));
+                    }
+                }
+                Event::NeedsInput => match input.next().await {
+                    Some(value) => self.provide_input(value),
+                    None => return Err(self.error(ErrorKind::InputExhausted)),
+                },
+                Event::Halted => return Ok(ExitReason::Halted),
+            }
+        }
+    }
+
+    fn error(&self, kind: ErrorKind) -> IntcodeError {
+        IntcodeError::new(self.ip, self.data[self.ip], kind)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::empty;
+    use futures::channel::mpsc;
+    use futures::executor::LocalPool;
+    use futures::task::LocalSpawnExt;
+    use futures::{stream, FutureExt};
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[test]
+    fn amplifier_feedback_loop() {
+        let program = [
+            3, 26, 1001, 26, -4, 26, 3, 27, 1002, 27, 2, 27, 1, 27, 26, 27, 4, 27, 1001, 28, -1,
+            28, 1005, 28, 6, 99, 0, 0, 5,
+        ];
+
+        let mut pool = LocalPool::new();
+        let (first, mut input) = mpsc::unbounded();
+        first.unbounded_send(9).unwrap();
+        first.unbounded_send(0).unwrap();
+
+        for &next_phase in &[Some(8), Some(7), Some(6), Some(5), None] {
+            let (sender, receiver) = mpsc::unbounded();
+            if let Some(phase) = next_phase {
+                sender.unbounded_send(phase).unwrap();
+            }
+            pool.spawner()
+                .spawn_local(async move {
+                    let mut machine = IntcodeMachine::new(&program, empty(), ());
+                    machine.run_async(input, sender).await.unwrap();
+                })
+                .unwrap();
+            input = receiver;
+        }
+
+        // The last amplifier feeds back into the first, which has already
+        // halted by the time the final signal arrives.
+        let signal = Rc::new(Cell::new(0));
+        let last = signal.clone();
+        pool.spawner()
+            .spawn_local(async move {
+                while let Some(value) = input.next().await {
+                    last.set(value);
+                    let _ = first.unbounded_send(value);
+                }
+            })
+            .unwrap();
+
+        pool.run();
+
+        assert_eq!(signal.get(), 139_629_729);
+    }
+
+    #[test]
+    fn thousands_of_machines() {
+        // Adds one to its input.
+        let program = [3, 9, 1001, 9, 1, 9, 4, 9, 99, 0];
+        let count = 5_000;
+
+        let mut pool = LocalPool::new();
+        let (first, mut input) = mpsc::unbounded();
+
+        for _ in 0..count {
+            let (sender, receiver) = mpsc::unbounded();
+            pool.spawner()
+                .spawn_local(async move {
+                    let mut machine = IntcodeMachine::new(&program, empty(), ());
+                    machine.run_async(input, sender).await.unwrap();
+                })
+                .unwrap();
+            input = receiver;
+        }
+
+        first.unbounded_send(0).unwrap();
+        drop(first);
+        pool.run();
+
+        assert_eq!(input.try_recv().ok(), Some(count));
+    }
+
+    #[test]
+    fn closed_streams() {
+        let mut machine = IntcodeMachine::new(&[3, 0, 99], empty(), ());
+        let error =
+            machine.run_async(stream::empty(), futures::sink::drain()).now_or_never().unwrap();
+        assert_eq!(error.unwrap_err().kind, ErrorKind::InputExhausted);
+
+        let (sender, receiver) = mpsc::unbounded();
+        drop(receiver);
+        let mut machine = IntcodeMachine::new(&[104, 1, 99], empty(), ());
+        let error = machine.run_async(stream::empty(), sender).now_or_never().unwrap();
+        assert_eq!(error.unwrap_err(), IntcodeError::new(2, 99, ErrorKind::OutputClosed));
+    }
+}
@@ -0,0 +1,58 @@
+//! Decoded instructions, kept per address so hot code is only decoded once.
+//!
+//! Intcode programs are free to modify themselves, so every memory write
+//! drops any cached instruction that the written cell is part of. An
+//! instruction is at most `MAX_SIZE` words long, so that's the entries
+//! starting up to `MAX_SIZE - 1` cells before it.
+
+use super::{Instructions, Word};
+
+pub(crate) const MAX_SIZE: usize = 4;
+/// Instructions at or past this address are decoded every time, so a jump
+/// far into sparse memory doesn't allocate a huge table.
+pub(crate) const MAX_ADDRESS: usize = 1 << 20;
+
+#[derive(Clone, Debug)]
+pub(crate) struct DecodeCache<N> {
+    slots: Vec<Option<Instructions<N>>>,
+}
+
+impl<N: Word> DecodeCache<N> {
+    pub(crate) fn new() -> Self {
+        Self { slots: Vec::new() }
+    }
+
+    #[inline]
+    pub(crate) fn get(&self, address: usize) -> Option<Instructions<N>> {
+        self.slots.get(address)?.clone()
+    }
+
+    pub(crate) fn insert(&mut self, address: usize, instruction: Instructions<N>) {
+        if address >= MAX_ADDRESS {
+            return;
+        }
+
+        if address >= self.slots.len() {
+            self.slots.resize(address + 1, None);
+        }
+
+        self.slots[address] = Some(instruction);
+    }
+
+    /// Forgets every instruction that covers `address`.
+    #[inline]
+    pub(crate) fn invalidate(&mut self, address: usize) {
+        let start = address.saturating_sub(MAX_SIZE - 1);
+        let end = (address + 1).min(self.slots.len());
+
+        if start < end {
+            for slot in &mut self.slots[start..end] {
+                *slot = None;
+            }
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.slots.clear();
+    }
+}
@@ -0,0 +1,470 @@
+//! Control-flow graph recovery.
+//!
+//! Code is discovered by following control flow from address 0, so data
+//! mixed in with the code is never decoded. Jumps with an immediate target
+//! are resolved; a jump whose test is an immediate is treated as always or
+//! never taken. Anything else is an indirect jump that can't be resolved
+//! statically, which gets an edge to a single `Node::Unresolved`.
+//!
+//! Compiled Intcode keeps its stack at the relative base, so subroutines are
+//! recognised by convention: a call stores the address after an
+//! unconditional jump through a relative operand before making the jump,
+//! and a return is a jump that can be taken whose target is a relative
+//! operand. A jump that is never taken just falls into the next block.
+
+use super::{Destination, Instruction, Instructions, Operand};
+use petgraph::dot::Dot;
+use petgraph::graph::{Graph, NodeIndex};
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+use std::fmt;
+
+#[derive(Clone, Debug)]
+pub struct Block {
+    pub start: usize,
+    /// Each instruction in the block with its address.
+    pub instructions: Vec<(usize, Instructions)>,
+    pub exit: Exit,
+}
+
+impl Block {
+    /// One past the last address covered by the block.
+    pub fn end(&self) -> usize {
+        self.instructions.last().map_or(self.start, |(address, i)| address + i.size())
+    }
+}
+
+/// How control leaves a block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Exit {
+    /// Runs into the next block.
+    Next,
+    Jump,
+    Call,
+    Return,
+    /// Jumps to an address computed at runtime.
+    Indirect,
+    Halt,
+    /// Ends in something that doesn't decode.
+    Invalid,
+}
+
+#[derive(Clone, Debug)]
+pub enum Node {
+    Block(Block),
+    /// Stands in for the targets of every unresolved indirect jump.
+    Unresolved,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Edge {
+    /// Falls into the next block, including a conditional jump not taken.
+    FallThrough,
+    /// A jump with an immediate target being taken.
+    Jump,
+    Call,
+    /// From a call to the instruction its subroutine returns to.
+    CallReturn,
+    Indirect,
+}
+
+pub struct ControlFlowGraph {
+    graph: Graph<Node, Edge>,
+    blocks: BTreeMap<usize, NodeIndex>,
+    unresolved: Option<NodeIndex>,
+    /// Subroutine entry points and the addresses of the jumps calling them.
+    subroutines: BTreeMap<usize, Vec<usize>>,
+}
+
+/// What an instruction does to control flow.
+enum Flow {
+    Next,
+    Halt,
+    Jump { taken: Taken, target: Operand },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Taken {
+    Always,
+    Never,
+    Maybe,
+}
+
+impl ControlFlowGraph {
+    /// Recovers the control-flow graph of `program`, starting at address 0.
+    pub fn build(program: &[isize]) -> Self {
+        let mut decoded = BTreeMap::new();
+        let mut leaders = BTreeSet::new();
+        let mut calls = BTreeMap::new();
+        let mut work = vec![0];
+
+        leaders.insert(0);
+
+        while let Some(start) = work.pop() {
+            // Immediates stored through relative operands since `start`, any
+            // of which may be the return address of a call.
+            let mut stored = Vec::new();
+            let mut ip = start;
+
+            while !decoded.contains_key(&ip) {
+                let instruction = match decode(program, ip) {
+                    Some(instruction) => instruction,
+                    None => break,
+                };
+                decoded.insert(ip, instruction);
+                let next = ip + instruction.size();
+
+                match flow(&instruction) {
+                    Flow::Next => {
+                        stored.extend(stored_immediate(&instruction));
+                        ip = next;
+                    }
+                    Flow::Halt => break,
+                    Flow::Jump { taken, target } => {
+                        if taken != Taken::Always {
+                            leaders.insert(next);
+                            work.push(next);
+                        }
+
+                        if let (Taken::Always | Taken::Maybe, Operand::Immediate(target)) =
+                            (taken, target)
+                        {
+                            // Jumps out of the image are left without a target.
+                            let target = usize::try_from(target)
+                                .ok()
+                                .filter(|&target| target < program.len());
+
+                            if let Some(target) = target {
+                                leaders.insert(target);
+                                work.push(target);
+
+                                if taken == Taken::Always && stored.contains(&(next as isize)) {
+                                    calls.insert(ip, target);
+                                    leaders.insert(next);
+                                    work.push(next);
+                                }
+                            }
+                        }
+
+                        break;
+                    }
+                }
+            }
+        }
+
+        let mut graph = Graph::new();
+        let mut blocks = BTreeMap::new();
+
+        for &start in &leaders {
+            let mut instructions = Vec::new();
+            let mut ip = start;
+
+            let exit = loop {
+                let instruction = match decoded.get(&ip) {
+                    Some(&instruction) => instruction,
+                    None => break Exit::Invalid,
+                };
+                instructions.push((ip, instruction));
+
+                match flow(&instruction) {
+                    Flow::Halt => break Exit::Halt,
+                    Flow::Jump { taken: Taken::Never, .. } => break Exit::Next,
+                    Flow::Jump { target: Operand::Relative(_), .. } => break Exit::Return,
+                    Flow::Jump { target: Operand::Position(_), .. } => break Exit::Indirect,
+                    Flow::Jump { .. } if calls.contains_key(&ip) => break Exit::Call,
+                    Flow::Jump { .. } => break Exit::Jump,
+                    Flow::Next => {}
+                }
+
+                ip += instruction.size();
+                if leaders.contains(&ip) {
+                    break Exit::Next;
+                }
+            };
+
+            let node = graph.add_node(Node::Block(Block { start, instructions, exit }));
+            blocks.insert(start, node);
+        }
+
+        let mut subroutines = BTreeMap::<usize, Vec<usize>>::new();
+        let mut edges = Vec::new();
+
+        for (&start, &node) in &blocks {
+            let block = match &graph[node] {
+                Node::Block(block) => block,
+                Node::Unresolved => unreachable!(),
+            };
+            let next = block.end();
+            let target = |address: isize| {
+                usize::try_from(address).ok().and_then(|address| blocks.get(&address).copied())
+            };
+
+            match block.instructions.last().map(|(_, i)| flow(i)) {
+                _ if block.exit == Exit::Next => {
+                    edges.push((node, Some(blocks[&next]), Edge::FallThrough))
+                }
+                Some(Flow::Jump { taken, target: jump_to }) => {
+                    if taken != Taken::Always {
+                        edges.push((node, Some(blocks[&next]), Edge::FallThrough));
+                    }
+
+                    match (taken, jump_to) {
+                        (Taken::Never, _) => {}
+                        (_, Operand::Immediate(address)) if block.exit == Exit::Call => {
+                            let (jump, _) = block.instructions.last().unwrap();
+                            subroutines.entry(calls[jump]).or_default().push(*jump);
+                            edges.push((node, target(address), Edge::Call));
+                            edges.push((node, Some(blocks[&next]), Edge::CallReturn));
+                        }
+                        (_, Operand::Immediate(address)) => {
+                            edges.push((node, target(address), Edge::Jump))
+                        }
+                        (_, Operand::Position(_)) => edges.push((node, None, Edge::Indirect)),
+                        (_, Operand::Relative(_)) => {}
+                    }
+                }
+                _ => {}
+            }
+
+            debug_assert_eq!(start, block.start);
+        }
+
+        let mut unresolved = None;
+
+        for (from, to, edge) in edges {
+            let to = match (to, edge) {
+                (Some(to), _) => to,
+                (None, Edge::Indirect) => {
+                    *unresolved.get_or_insert_with(|| graph.add_node(Node::Unresolved))
+                }
+                // A jump to an address that isn't in the image.
+                (None, _) => continue,
+            };
+            graph.add_edge(from, to, edge);
+        }
+
+        Self { graph, blocks, unresolved, subroutines }
+    }
+
+    pub fn graph(&self) -> &Graph<Node, Edge> {
+        &self.graph
+    }
+
+    /// Blocks in address order.
+    pub fn blocks(&self) -> impl Iterator<Item = &Block> + '_ {
+        self.blocks.values().map(move |&node| match &self.graph[node] {
+            Node::Block(block) => block,
+            Node::Unresolved => unreachable!(),
+        })
+    }
+
+    pub fn block(&self, start: usize) -> Option<&Block> {
+        self.blocks().find(|block| block.start == start)
+    }
+
+    /// The edges leaving the block at `start`, as the start of the target
+    /// block (`None` for an unresolved target) and the kind of edge.
+    pub fn successors(&self, start: usize) -> Vec<(Option<usize>, Edge)> {
+        let node = match self.blocks.get(&start) {
+            Some(&node) => node,
+            None => return Vec::new(),
+        };
+
+        let mut successors: Vec<_> = self
+            .graph
+            .edges(node)
+            .map(|edge| {
+                use petgraph::visit::EdgeRef;
+
+                let target = match &self.graph[edge.target()] {
+                    Node::Block(block) => Some(block.start),
+                    Node::Unresolved => None,
+                };
+                (target, *edge.weight())
+            })
+            .collect();
+        successors.sort_by_key(|&(target, _)| target);
+        successors
+    }
+
+    /// Whether any indirect jump couldn't be resolved.
+    pub fn has_unresolved(&self) -> bool {
+        self.unresolved.is_some()
+    }
+
+    /// Subroutine entry points, each with the addresses that call it.
+    pub fn subroutines(&self) -> &BTreeMap<usize, Vec<usize>> {
+        &self.subroutines
+    }
+
+    /// Renders the graph in Graphviz DOT format.
+    pub fn to_dot(&self) -> String {
+        format!("{:#}", Dot::new(&self.graph))
+    }
+}
+
+impl fmt::Display for Block {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "block {}", self.start)?;
+
+        for (address, instruction) in &self.instructions {
+            write!(f, "\n{:>5}: {}", address, instruction)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Node::Block(block) => block.fmt(f),
+            Node::Unresolved => f.write_str("unresolved"),
+        }
+    }
+}
+
+impl fmt::Display for Edge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Edge::FallThrough => "",
+            Edge::Jump => "jump",
+            Edge::Call => "call",
+            Edge::CallReturn => "returns",
+            Edge::Indirect => "indirect",
+        })
+    }
+}
+
+fn decode(program: &[isize], ip: usize) -> Option<Instructions> {
+    Instructions::decode(program.get(ip..)?, ip).ok()
+}
+
+fn flow(instruction: &Instructions) -> Flow {
+    let (test, target, if_nonzero) = match instruction {
+        Instructions::Halt(_) => return Flow::Halt,
+        Instructions::JumpIfTrue(i) => (i.test, i.jump_to, true),
+        Instructions::JumpIfFalse(i) => (i.test, i.jump_to, false),
+        _ => return Flow::Next,
+    };
+
+    let taken = match test {
+        Operand::Immediate(n) if (n != 0) == if_nonzero => Taken::Always,
+        Operand::Immediate(_) => Taken::Never,
+        _ => Taken::Maybe,
+    };
+
+    Flow::Jump { taken, target }
+}
+
+/// The value an instruction stores through a relative destination, if it's
+/// known statically.
+fn stored_immediate(instruction: &Instructions) -> Option<isize> {
+    match instruction {
+        Instructions::Add(i) => match (i.op1, i.op2, i.dst) {
+            (Operand::Immediate(a), Operand::Immediate(b), Destination::Relative(_)) => {
+                a.checked_add(b)
+            }
+            _ => None,
+        },
+        Instructions::Mul(i) => match (i.op1, i.op2, i.dst) {
+            (Operand::Immediate(a), Operand::Immediate(b), Destination::Relative(_)) => {
+                a.checked_mul(b)
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::asm;
+
+    #[test]
+    fn loops_and_calls() {
+        let program = asm::assemble(
+            "
+        arel imm(stack)
+        input pos(n)
+loop:   add imm(next), imm(0), rel(0)
+        jit imm(1), imm(double)
+next:   add pos(n), imm(-1), pos(n)
+        jit pos(n), imm(loop)
+        output pos(sum)
+        halt
+double: add pos(sum), pos(sum), pos(sum)
+        jif imm(0), rel(0)
+n:      .data 0
+sum:    .data 1
+stack:  .data 0
+",
+        )
+        .unwrap();
+        let cfg = ControlFlowGraph::build(&program);
+
+        let starts: Vec<_> = cfg.blocks().map(|b| (b.start, b.exit)).collect();
+        assert_eq!(
+            starts,
+            vec![
+                (0, Exit::Next),
+                (4, Exit::Call),
+                (11, Exit::Jump),
+                (18, Exit::Halt),
+                (21, Exit::Return)
+            ]
+        );
+
+        assert_eq!(cfg.successors(0), vec![(Some(4), Edge::FallThrough)]);
+        assert_eq!(cfg.successors(4), vec![(Some(11), Edge::CallReturn), (Some(21), Edge::Call)]);
+        assert_eq!(cfg.successors(11), vec![(Some(4), Edge::Jump), (Some(18), Edge::FallThrough)]);
+        assert_eq!(cfg.successors(21), vec![]);
+        assert_eq!(cfg.subroutines(), &vec![(21, vec![8])].into_iter().collect());
+        assert!(!cfg.has_unresolved());
+
+        // The data after the subroutine is never decoded.
+        assert_eq!(cfg.block(21).unwrap().end(), 28);
+        assert_eq!(program.len(), 31);
+
+        let dot = cfg.to_dot();
+        assert!(dot.starts_with("digraph {"));
+        assert!(dot.contains("block 21\\l   21: add pos(29), pos(29), pos(29)\\l"));
+        assert!(dot.contains("[label=\"call\\l\"]"));
+    }
+
+    #[test]
+    fn indirect_jump() {
+        let cfg = ControlFlowGraph::build(&[3, 7, 6, 7, 8, 99, 0, 0, 5]);
+
+        assert!(cfg.has_unresolved());
+        assert_eq!(cfg.block(0).unwrap().exit, Exit::Indirect);
+        assert_eq!(cfg.successors(0), vec![(None, Edge::Indirect), (Some(5), Edge::FallThrough)]);
+    }
+
+    #[test]
+    fn stored_overflow() {
+        let cfg = ControlFlowGraph::build(&[21101, isize::MAX, 1, 0, 1105, 1, 0]);
+
+        assert_eq!(cfg.block(0).unwrap().exit, Exit::Jump);
+        assert_eq!(cfg.successors(0), vec![(Some(0), Edge::Jump)]);
+    }
+
+    #[test]
+    fn call_out_of_image() {
+        let cfg = ControlFlowGraph::build(&[21101, 7, 0, 0, 1105, 1, -1, 99]);
+
+        assert_eq!(cfg.block(0).unwrap().exit, Exit::Jump);
+        assert_eq!(cfg.successors(0), vec![]);
+        assert!(cfg.subroutines().is_empty());
+    }
+
+    #[test]
+    fn return_never_taken() {
+        let cfg = ControlFlowGraph::build(&[2106, 1, 0, 99]);
+
+        assert_eq!(cfg.block(0).unwrap().exit, Exit::Next);
+        assert_eq!(cfg.successors(0), vec![(Some(3), Edge::FallThrough)]);
+        assert_eq!(cfg.block(3).unwrap().exit, Exit::Halt);
+    }
+}
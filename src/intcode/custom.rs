@@ -0,0 +1,388 @@
+//! Opcodes beyond the standard set, registered per machine.
+//!
+//! A custom instruction is a type implementing `Custom`, which says how many
+//! parameters it takes, how to build it from them and how to execute it.
+//! `IntcodeMachine::with_opcode` adds it under an opcode the standard set
+//! doesn't use, and from then on that machine decodes it wherever the opcode
+//! appears. Its `execute` gets the machine as a `Machine`: `read`, `write`,
+//! `input`, `output`, `set_ip`, `halt` and so on. That doesn't depend on the
+//! machine's input, output or tracer types, so custom opcodes stay when
+//! `with_tracer` replaces the tracer. Like the machine, `Custom` is generic
+//! over the word type and defaults to `isize`.
+//!
+//! The `Custom` type is built from its parameters each time the instruction
+//! runs, and the threaded engine leaves custom opcodes to the interpreter.
+//! The disassembler, control flow graph and transpiler only know the
+//! standard set and treat custom opcodes as invalid.
+
+use super::{
+    Destination, ErrorKind, Instruction, Instructions, IntcodeMachine, Mode, Opcode, Operand, Sink,
+    Source, Tracer, Word,
+};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::sync::Arc;
+
+/// What a custom instruction can do to the machine running it. Each method
+/// behaves like the `IntcodeMachine` method of the same name.
+pub trait Machine<N = isize> {
+    fn read(&mut self, address: usize) -> Result<N, ErrorKind>;
+    fn write(&mut self, address: usize, value: N) -> Result<(), ErrorKind>;
+    fn input(&mut self) -> Result<N, ErrorKind>;
+    fn output(&mut self, value: N);
+    fn halt(&mut self);
+    /// The address of the next instruction.
+    fn ip(&self) -> usize;
+    fn set_ip(&mut self, ip: usize);
+    fn relative_base(&self) -> N;
+    fn set_relative_base(&mut self, relative_base: N);
+    /// The address a relative mode parameter of `offset` refers to.
+    fn relative(&self, offset: &N) -> Result<usize, ErrorKind>;
+}
+
+impl<R: Source<N>, W: Sink<N>, T: Tracer<N>, N: Word> Machine<N> for IntcodeMachine<R, W, T, N> {
+    fn read(&mut self, address: usize) -> Result<N, ErrorKind> {
+        IntcodeMachine::read(self, address)
+    }
+
+    fn write(&mut self, address: usize, value: N) -> Result<(), ErrorKind> {
+        IntcodeMachine::write(self, address, value)
+    }
+
+    fn input(&mut self) -> Result<N, ErrorKind> {
+        IntcodeMachine::input(self)
+    }
+
+    fn output(&mut self, value: N) {
+        IntcodeMachine::output(self, value)
+    }
+
+    fn halt(&mut self) {
+        IntcodeMachine::halt(self)
+    }
+
+    fn ip(&self) -> usize {
+        IntcodeMachine::ip(self)
+    }
+
+    fn set_ip(&mut self, ip: usize) {
+        IntcodeMachine::set_ip(self, ip)
+    }
+
+    fn relative_base(&self) -> N {
+        IntcodeMachine::relative_base(self).clone()
+    }
+
+    fn set_relative_base(&mut self, relative_base: N) {
+        IntcodeMachine::set_relative_base(self, relative_base)
+    }
+
+    #[inline]
+    fn relative(&self, offset: &N) -> Result<usize, ErrorKind> {
+        IntcodeMachine::relative(self, offset)
+    }
+}
+
+/// An instruction that can be added to a machine with
+/// `IntcodeMachine::with_opcode`.
+pub trait Custom<N = isize>: Sized {
+    /// Shown in place of an opcode, e.g. by tracers.
+    const MNEMONIC: &'static str;
+    /// How many parameters follow the opcode word. At most 3, since that's
+    /// how many modes an opcode word holds.
+    const PARAMS: usize;
+
+    /// Builds the instruction from its `PARAMS` parameters.
+    fn decode(params: &[Param<N>]) -> Result<Self, ErrorKind>;
+
+    /// Runs the instruction. The instruction pointer has already been moved
+    /// past it.
+    ///
+    /// If this fails, the instruction pointer is moved back and the whole
+    /// instruction runs again when the machine resumes, e.g. after
+    /// suspending for input. Writes and outputs made before the failure
+    /// aren't undone, so read all input before making either.
+    fn execute(&self, machine: &mut dyn Machine<N>) -> Result<(), ErrorKind>;
+}
+
+/// A raw parameter and the mode the opcode word gives it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Param<N = isize> {
+    mode: Mode,
+    value: N,
+}
+
+impl<N: Word> Param<N> {
+    pub fn operand(&self) -> Result<Operand<N>, ErrorKind> {
+        Operand::from_parts(self.mode, self.value.clone())
+    }
+
+    pub fn destination(&self) -> Result<Destination<N>, ErrorKind> {
+        Destination::from_parts(self.mode, self.value.clone())
+    }
+}
+
+impl<N: fmt::Display> fmt::Display for Param<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.mode {
+            Mode::Immediate => write!(f, "imm({})", self.value),
+            Mode::Position => write!(f, "pos({})", self.value),
+            Mode::Relative => write!(f, "rel({})", self.value),
+        }
+    }
+}
+
+/// A custom instruction as decoded by a machine. Executing it looks up the
+/// machine's `Custom` type for the opcode and builds one from the
+/// parameters.
+#[derive(Clone, Copy, Debug)]
+pub struct Extension<N = isize> {
+    opcode: usize,
+    mnemonic: &'static str,
+    params: [Param<N>; 3],
+    len: usize,
+}
+
+impl<N> Extension<N> {
+    pub fn opcode(&self) -> usize {
+        self.opcode
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        self.mnemonic
+    }
+
+    pub fn params(&self) -> &[Param<N>] {
+        &self.params[..self.len]
+    }
+}
+
+impl<N: Word> Instruction<N> for Extension<N> {
+    fn execute<R: Source<N>, W: Sink<N>, T: Tracer<N>>(
+        &self,
+        machine: &mut IntcodeMachine<R, W, T, N>,
+    ) -> Result<(), ErrorKind> {
+        let handler = match machine.opcodes.entries.get(&self.opcode) {
+            Some(entry) => entry.handler.clone(),
+            None => return Err(ErrorKind::InvalidOpcode(self.opcode as isize)),
+        };
+
+        handler(machine, self.params())
+    }
+
+    fn size(&self) -> usize {
+        1 + self.len
+    }
+}
+
+impl<N: fmt::Display> fmt::Display for Extension<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.mnemonic)?;
+
+        for (i, param) in self.params().iter().enumerate() {
+            write!(f, "{}{}", if i == 0 { " " } else { ", " }, param)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Runs a custom instruction from its parameters. Shared between clones of
+/// a machine, along with anything it captures.
+pub(crate) type Handler<N = isize> =
+    Arc<dyn Fn(&mut dyn Machine<N>, &[Param<N>]) -> Result<(), ErrorKind> + Send + Sync>;
+
+struct Entry<N> {
+    mnemonic: &'static str,
+    params: usize,
+    handler: Handler<N>,
+}
+
+impl<N> Clone for Entry<N> {
+    fn clone(&self) -> Self {
+        Self { mnemonic: self.mnemonic, params: self.params, handler: self.handler.clone() }
+    }
+}
+
+/// The custom opcodes registered with a machine.
+pub(crate) struct Opcodes<N> {
+    entries: HashMap<usize, Entry<N>>,
+}
+
+impl<N> Clone for Opcodes<N> {
+    fn clone(&self) -> Self {
+        Self { entries: self.entries.clone() }
+    }
+}
+
+impl<N: Word> Opcodes<N> {
+    pub(crate) fn new() -> Self {
+        Self { entries: HashMap::new() }
+    }
+
+    pub(crate) fn insert<I: Custom<N>>(&mut self, opcode: usize) {
+        let execute: fn(&mut dyn Machine<N>, &[Param<N>]) -> _ = execute::<I, N>;
+        self.insert_handler(opcode, I::MNEMONIC, I::PARAMS, Arc::new(execute));
+    }
+
+    /// Registers `handler` under `opcode`, for instructions that need more
+    /// than a `Custom` type can hold, like the host's callbacks.
+    pub(crate) fn insert_handler(
+        &mut self,
+        opcode: usize,
+        mnemonic: &'static str,
+        params: usize,
+        handler: Handler<N>,
+    ) {
+        self.entries.insert(opcode, Entry { mnemonic, params, handler });
+    }
+
+    /// Decodes the instruction at the start of `ints` if its opcode is one
+    /// of these.
+    pub(crate) fn decode(&self, ints: &[N; 4]) -> Option<Instructions<N>> {
+        if self.entries.is_empty() {
+            return None;
+        }
+
+        let opcode = Opcode::try_from(ints[0].to_isize()?).ok()?;
+        let entry = self.entries.get(&opcode.opcode)?;
+
+        let modes = [opcode.param1, opcode.param2, opcode.param3];
+        let params = [0, 1, 2].map(|i| {
+            if i < entry.params {
+                Param { mode: modes[i], value: ints[i + 1].clone() }
+            } else {
+                Param { mode: Mode::Position, value: N::ZERO.clone() }
+            }
+        });
+
+        let extension = Extension {
+            opcode: opcode.opcode,
+            mnemonic: entry.mnemonic,
+            params,
+            len: entry.params,
+        };
+
+        Some(extension.into())
+    }
+}
+
+fn execute<I: Custom<N>, N>(
+    machine: &mut dyn Machine<N>,
+    params: &[Param<N>],
+) -> Result<(), ErrorKind> {
+    I::decode(params)?.execute(machine)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::fixture::run_on_every_engine;
+    use crate::intcode::trace::TextTracer;
+    use crate::intcode::{empty, Event, ExitReason, IntcodeError, Step};
+
+    /// `print a, b`: outputs `a` followed by `b`.
+    struct Print(Operand, Operand);
+
+    impl Custom for Print {
+        const MNEMONIC: &'static str = "print";
+        const PARAMS: usize = 2;
+
+        fn decode(params: &[Param]) -> Result<Self, ErrorKind> {
+            Ok(Print(params[0].operand()?, params[1].operand()?))
+        }
+
+        fn execute(&self, machine: &mut dyn Machine) -> Result<(), ErrorKind> {
+            let a = self.0.resolve(machine)?;
+            let b = self.1.resolve(machine)?;
+
+            machine.output(a);
+            machine.output(b);
+
+            Ok(())
+        }
+    }
+
+    /// `square dst`: squares the value at `dst` in place.
+    struct Square(Destination);
+
+    impl Custom for Square {
+        const MNEMONIC: &'static str = "square";
+        const PARAMS: usize = 1;
+
+        fn decode(params: &[Param]) -> Result<Self, ErrorKind> {
+            Ok(Square(params[0].destination()?))
+        }
+
+        fn execute(&self, machine: &mut dyn Machine) -> Result<(), ErrorKind> {
+            let dst = self.0.resolve(machine)?;
+            let value = machine.read(dst)?;
+
+            machine.write(dst, value * value)
+        }
+    }
+
+    #[test]
+    fn custom_opcodes() {
+        // square pos(9), square pos(9), print pos(9), imm(-1), halt
+        let program = [50, 9, 50, 9, 1051, 9, -1, 99, 0, 3];
+
+        let result = run_on_every_engine(|| {
+            IntcodeMachine::new(&program, empty(), Vec::new())
+                .with_opcode::<Square>(50)
+                .with_opcode::<Print>(51)
+        });
+        assert_eq!(result, (Ok(ExitReason::Halted), vec![81, -1]));
+
+        let mut output = Vec::new();
+        let result = IntcodeMachine::new(&program, empty(), &mut output).run();
+        assert_eq!(result, Err(IntcodeError::new(0, 50, ErrorKind::InvalidOpcode(50))));
+    }
+
+    #[test]
+    fn decode_and_faults() {
+        let program = [1151, 9, -1, 1150, 9, 99];
+        let mut machine = IntcodeMachine::new(&program, empty(), ())
+            .with_opcode::<Print>(51)
+            .with_opcode::<Square>(50);
+
+        let Step { instruction, ip_after, .. } = machine.step().unwrap();
+        assert_eq!(instruction.to_string(), "print imm(9), imm(-1)");
+        assert_eq!(ip_after, 3);
+
+        assert_eq!(machine.run(), Err(IntcodeError::new(3, 1150, ErrorKind::ImmediateDestination)));
+    }
+
+    #[test]
+    fn kept_by_with_tracer() {
+        let program = [50, 5, 1051, 5, -1, 3];
+        let mut tracer = TextTracer::new(vec![]);
+        let mut machine = IntcodeMachine::new(&program, empty(), Vec::new())
+            .with_opcode::<Square>(50)
+            .with_opcode::<Print>(51)
+            .with_tracer(&mut tracer);
+
+        assert_eq!(machine.run_for(2), Ok(ExitReason::BudgetExhausted));
+        assert_eq!(machine.output_mut(), &[9, -1]);
+
+        let trace = String::from_utf8(tracer.into_inner().unwrap()).unwrap();
+        assert!(trace.contains("    2: print pos(5), imm(-1)"), "{}", trace);
+    }
+
+    #[test]
+    fn every_output_is_an_event() {
+        let program = [1151, 7, -1, 1151, 8, 9, 99];
+        let mut machine =
+            IntcodeMachine::new(&program, empty(), Vec::new()).with_opcode::<Print>(51);
+
+        let mut events = Vec::new();
+        while let Event::Output(value) = machine.run_until_event().unwrap() {
+            events.push(value);
+        }
+
+        assert_eq!(events, [7, -1, 8, 9]);
+        assert_eq!(machine.output_mut(), &[7, -1, 8, 9]);
+        assert!(machine.is_halted());
+    }
+}
@@ -0,0 +1,366 @@
+//! Command interpreter behind the `debugger` binary.
+//!
+//! The machine is driven one `step` at a time so breakpoints (checked against
+//! `ip` before each instruction) and watchpoints (checked against the memory
+//! writes each step reports) can stop execution. Input is only ever supplied
+//! by hand with the `input` command.
+
+use super::{disasm, empty, ErrorKind, IntcodeMachine, Step};
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::iter::Empty;
+
+pub const HELP: &str = "\
+commands:
+  s, step [n]           execute n instructions (default 1)
+  n, next               run until the instruction after the current one
+  c, continue           run until a breakpoint, watchpoint, input request or halt
+  b, break <addr>       set a breakpoint
+  w, watch <addr>       stop whenever <addr> is written
+  d, delete <addr>      remove a breakpoint or watchpoint
+  info                  list breakpoints and watchpoints
+  l, list [addr] [n]    disassemble n lines from addr (default: ip)
+  x <addr> [n]          show n memory cells starting at addr
+  set <addr> <value>    write a memory cell
+  ip [addr]             show or set the instruction pointer
+  rb [value]            show or set the relative base
+  i, input <values..>   queue input values
+  q, quit               exit";
+
+/// The most cells one `x` command shows.
+const MAX_EXAMINE: usize = 1024;
+
+pub struct Debugger {
+    machine: IntcodeMachine<Empty<isize>, Vec<isize>>,
+    breakpoints: BTreeSet<usize>,
+    watchpoints: BTreeSet<usize>,
+}
+
+/// Why a `step`, `next` or `continue` stopped.
+enum Stop {
+    Done,
+    Breakpoint,
+    Watchpoint(usize, isize, isize),
+    NeedsInput,
+    Halted,
+    Fault(String),
+}
+
+impl Debugger {
+    pub fn new(program: &[isize]) -> Self {
+        Self::from_machine(IntcodeMachine::new(program, empty(), Vec::new()))
+    }
+
+    pub fn from_machine(machine: IntcodeMachine<Empty<isize>, Vec<isize>>) -> Self {
+        Self { machine, breakpoints: BTreeSet::new(), watchpoints: BTreeSet::new() }
+    }
+
+    pub fn machine(&self) -> &IntcodeMachine<Empty<isize>, Vec<isize>> {
+        &self.machine
+    }
+
+    /// Runs a single command line and returns the text to show the user.
+    pub fn execute(&mut self, line: &str) -> String {
+        let mut words = line.split_whitespace();
+        let command = match words.next() {
+            Some(command) => command,
+            None => return String::new(),
+        };
+        let args: Vec<&str> = words.collect();
+
+        let mut reply = match self.command(command, &args) {
+            Ok(reply) => reply,
+            Err(e) => format!("error: {}\n", e),
+        };
+
+        for value in self.machine.output_mut().drain(..) {
+            let _ = writeln!(reply, "output: {}", value);
+        }
+
+        reply
+    }
+
+    fn command(&mut self, command: &str, args: &[&str]) -> Result<String, String> {
+        match (command, args) {
+            ("s", _) | ("step", _) => {
+                let count = args.first().map(|n| parse(n)).transpose()?.unwrap_or(1);
+                let mut reply = String::new();
+                let mut stop = Stop::Done;
+
+                for _ in 0..count {
+                    match self.step() {
+                        Ok((step, watchpoint)) => {
+                            let _ = writeln!(reply, "{:>5}: {}", step.ip_before, step.instruction);
+
+                            if let Some(s) = watchpoint {
+                                stop = s;
+                                break;
+                            }
+                        }
+                        Err(s) => {
+                            stop = s;
+                            break;
+                        }
+                    }
+                }
+
+                reply.push_str(&self.describe(stop));
+                Ok(reply)
+            }
+            ("n", []) | ("next", []) => {
+                let target = self.next_address()?;
+                let stop = self.run_until(|debugger| debugger.machine.ip() == target);
+
+                Ok(self.describe(stop))
+            }
+            ("c", []) | ("continue", []) => {
+                let stop = self.run_until(|_| false);
+
+                Ok(self.describe(stop))
+            }
+            ("b", [address]) | ("break", [address]) => {
+                self.breakpoints.insert(parse(address)?);
+                Ok(String::new())
+            }
+            ("w", [address]) | ("watch", [address]) => {
+                self.watchpoints.insert(parse(address)?);
+                Ok(String::new())
+            }
+            ("d", [address]) | ("delete", [address]) => {
+                let address = parse(address)?;
+                if self.breakpoints.remove(&address) | self.watchpoints.remove(&address) {
+                    Ok(String::new())
+                } else {
+                    Err(format!("nothing set at {}", address))
+                }
+            }
+            ("info", []) => Ok(format!(
+                "breakpoints: {:?}\nwatchpoints: {:?}\n",
+                self.breakpoints, self.watchpoints
+            )),
+            ("l", _) | ("list", _) => {
+                let address = args.first().map(|n| parse(n)).transpose()?;
+                let count = args.get(1).map(|n| parse(n)).transpose()?.unwrap_or(10);
+
+                self.listing(address.unwrap_or_else(|| self.machine.ip()), 3, count)
+            }
+            ("x", [address]) => self.examine(parse(address)?, 1),
+            ("x", [address, count]) => self.examine(parse(address)?, parse(count)?),
+            ("set", [address, value]) => {
+                let (address, value) = (parse(address)?, parse(value)?);
+                self.machine.data_mut().write(address, value).map_err(|e| e.to_string())?;
+                Ok(String::new())
+            }
+            ("ip", []) => Ok(format!("ip = {}\n", self.machine.ip())),
+            ("ip", [ip]) => {
+                self.machine.set_ip(parse(ip)?);
+                Ok(String::new())
+            }
+            ("rb", []) => Ok(format!("relative_base = {}\n", self.machine.relative_base())),
+            ("rb", [rb]) => {
+                self.machine.set_relative_base(parse(rb)?);
+                Ok(String::new())
+            }
+            ("i", _) | ("input", _) if !args.is_empty() => {
+                for value in args {
+                    self.machine.provide_input(parse(value)?);
+                }
+
+                Ok(String::new())
+            }
+            ("h", []) | ("help", []) => Ok(format!("{}\n", HELP)),
+            _ => Err(format!("can't understand `{} {}`, try `help`", command, args.join(" "))),
+        }
+    }
+
+    /// Executes one instruction, also returning a stop if it wrote to a
+    /// watched cell.
+    fn step(&mut self) -> Result<(Step, Option<Stop>), Stop> {
+        let memory = self.machine.data();
+        let watched: Vec<_> = self.watchpoints.iter().map(|&a| (a, memory[a])).collect();
+
+        let step = self.machine.step().map_err(|e| match e.kind {
+            ErrorKind::InputExhausted => Stop::NeedsInput,
+            ErrorKind::Halted => Stop::Halted,
+            _ => Stop::Fault(e.to_string()),
+        })?;
+
+        let watchpoint = step.writes.iter().find_map(|&(address, new)| {
+            let &(_, old) = watched.iter().find(|(a, _)| *a == address)?;
+            Some(Stop::Watchpoint(address, old, new))
+        });
+
+        Ok((step, watchpoint))
+    }
+
+    /// Steps until `done` returns true or something else stops execution.
+    /// A breakpoint at the starting `ip` is skipped so `continue` makes
+    /// progress.
+    fn run_until(&mut self, done: impl Fn(&Self) -> bool) -> Stop {
+        loop {
+            if let Some(stop) = self.step().map_or_else(Some, |(_, watchpoint)| watchpoint) {
+                return stop;
+            }
+
+            if self.machine.is_halted() {
+                return Stop::Halted;
+            }
+
+            if done(self) {
+                return Stop::Done;
+            }
+
+            if self.breakpoints.contains(&self.machine.ip()) {
+                return Stop::Breakpoint;
+            }
+        }
+    }
+
+    fn next_address(&self) -> Result<usize, String> {
+        let ip = self.machine.ip();
+        let words: Vec<_> = (ip..ip + 4).map(|a| self.machine.data()[a]).collect();
+
+        match disasm::disassemble_at(&words, ip).first() {
+            Some(line @ disasm::Line::Instruction { .. }) => Ok(ip + line.len()),
+            _ => Err(format!("no instruction at {}", ip)),
+        }
+    }
+
+    fn describe(&self, stop: Stop) -> String {
+        let mut reply = match stop {
+            Stop::Done => String::new(),
+            Stop::Breakpoint => format!("breakpoint at {}\n", self.machine.ip()),
+            Stop::Watchpoint(address, old, new) => {
+                format!("watchpoint: memory[{}] {} -> {}\n", address, old, new)
+            }
+            Stop::NeedsInput => String::from("waiting for input, use `input <values..>`\n"),
+            Stop::Halted => return String::from("halted\n"),
+            Stop::Fault(e) => format!("fault: {}\n", e),
+        };
+
+        if let Ok(listing) = self.listing(self.machine.ip(), 0, 1) {
+            reply.push_str(&listing);
+        }
+
+        reply
+    }
+
+    fn examine(&self, address: usize, count: usize) -> Result<String, String> {
+        if count > MAX_EXAMINE {
+            return Err(format!("can't examine more than {} cells at once", MAX_EXAMINE));
+        }
+
+        let end = address
+            .checked_add(count)
+            .ok_or_else(|| format!("can't examine {} cells from {}", count, address))?;
+        let mut reply = String::new();
+
+        for address in address..end {
+            let _ = writeln!(reply, "{:>5}: {}", address, self.machine.data()[address]);
+        }
+
+        Ok(reply)
+    }
+
+    /// Disassembles up to `before` lines preceding `address`, followed by
+    /// `count` lines starting at it.
+    fn listing(&self, address: usize, before: usize, count: usize) -> Result<String, String> {
+        let memory = self.machine.data();
+        let end = count
+            .checked_mul(4)
+            .and_then(|words| address.checked_add(words))
+            .ok_or_else(|| format!("can't list {} instructions from {}", count, address))?;
+        // Past the end of memory is all zeros, so one line's worth will do.
+        let end = end.min(memory.len().max(address).saturating_add(4));
+        let window = |start: usize| {
+            let words: Vec<_> = (start..end).map(|a| memory[a]).collect();
+            disasm::disassemble_at(&words, start)
+        };
+
+        // Decoding backwards from `address` is ambiguous, so only show the
+        // preceding lines if a sweep from further back lines up with it.
+        let mut lines = window(address.saturating_sub(before * 4));
+        let position = match lines.iter().position(|line| line.address() == address) {
+            Some(position) => position,
+            None => {
+                lines = window(address);
+                0
+            }
+        };
+
+        let ip = self.machine.ip();
+        let mut reply = String::new();
+
+        for line in
+            lines.iter().skip(position.saturating_sub(before)).take(position.min(before) + count)
+        {
+            let breakpoint = if self.breakpoints.contains(&line.address()) { '*' } else { ' ' };
+            let current = if line.address() == ip { "=>" } else { "  " };
+            let _ = writeln!(reply, "{}{} {}", breakpoint, current, line);
+        }
+
+        Ok(reply)
+    }
+}
+
+fn parse<T: std::str::FromStr>(text: &str) -> Result<T, String> {
+    text.parse().map_err(|_| format!("`{}` isn't a valid number", text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::asm::assemble;
+
+    #[test]
+    fn breakpoints_watchpoints_and_input() {
+        let program = assemble(
+            "
+            loop:   input pos(100)
+                    add pos(100), pos(101), pos(101)
+                    output pos(101)
+                    jit imm(1), imm(loop)
+            ",
+        )
+        .unwrap();
+        let mut debugger = Debugger::new(&program);
+
+        assert!(debugger.execute("c").starts_with("waiting for input"));
+        debugger.execute("input 5 7");
+        debugger.execute("watch 101");
+
+        let reply = debugger.execute("c");
+        assert!(reply.starts_with("watchpoint: memory[101] 0 -> 5\n"), "{}", reply);
+        assert_eq!(debugger.machine().ip(), 6);
+
+        debugger.execute("delete 101");
+        debugger.execute("break 8");
+        let reply = debugger.execute("continue");
+        assert!(reply.starts_with("breakpoint at 8\n*=>     8: jit imm(1), imm(0)"), "{}", reply);
+        assert!(reply.ends_with("output: 5\n"), "{}", reply);
+
+        let reply = debugger.execute("step 4");
+        assert!(
+            reply.starts_with("    8: jit imm(1), imm(0)\n    0: input pos(100)\n"),
+            "{}",
+            reply
+        );
+        assert!(reply.ends_with("output: 12\n"), "{}", reply);
+
+        debugger.execute("set 101 -1");
+        debugger.execute("ip 6");
+        let reply = debugger.execute("n");
+        assert!(reply.starts_with("*=>     8: jit"), "{}", reply);
+        assert!(reply.ends_with("output: -1\n"), "{}", reply);
+
+        assert_eq!(debugger.execute("x 100 2"), "  100: 7\n  101: -1\n");
+        assert!(debugger.execute("bogus").starts_with("error: "));
+        assert!(debugger.execute("x 18446744073709551615 2").starts_with("error: "));
+        let reply = debugger.execute("x 0 18446744073709551615");
+        assert_eq!(reply, "error: can't examine more than 1024 cells at once\n");
+        assert_eq!(debugger.execute("x 0 1024").lines().count(), 1024);
+        assert!(debugger.execute("list 18446744073709551615 2").starts_with("error: "));
+        let reply = debugger.execute("list 0 99999999999999999");
+        assert!(reply.lines().count() < 102, "{}", reply);
+    }
+}
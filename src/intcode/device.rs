@@ -0,0 +1,308 @@
+//! Memory-mapped devices.
+//!
+//! `IntcodeMachine::with_device` maps a `Device` over a range of addresses.
+//! Instructions that read or write in that range talk to the device instead
+//! of memory, so a program can draw to a screen or poll a keyboard with
+//! ordinary instructions. Instructions are always fetched from memory, never
+//! from devices.
+//!
+//! Devices are shared with whoever mapped them through an `Arc<Mutex<_>>`,
+//! so the host can keep a handle and, say, render a framebuffer while or
+//! after the program runs.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Something that can be mapped into a machine's address space.
+pub trait Device<N = isize>: Send {
+    /// How many addresses the device takes up.
+    fn len(&self) -> usize;
+
+    /// Reads the word at `offset` into the device's range.
+    fn read(&mut self, offset: usize) -> N;
+
+    /// Writes the word at `offset` into the device's range.
+    fn write(&mut self, offset: usize, value: N);
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// A grid of cells, one word each, laid out row by row.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    cells: Vec<isize>,
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self { width, height, cells: vec![0; width * height] }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Option<isize> {
+        if x < self.width {
+            self.cells.get(y * self.width + x).copied()
+        } else {
+            None
+        }
+    }
+
+    /// Draws the screen as text, one line per row, using `glyph` for each
+    /// cell.
+    pub fn render(&self, glyph: impl Fn(isize) -> char) -> String {
+        let mut text = String::with_capacity((self.width + 1) * self.height);
+
+        for row in self.cells.chunks(self.width.max(1)) {
+            text.extend(row.iter().map(|&cell| glyph(cell)));
+            text.push('\n');
+        }
+
+        text
+    }
+}
+
+impl Device for Framebuffer {
+    fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    fn read(&mut self, offset: usize) -> isize {
+        self.cells[offset]
+    }
+
+    fn write(&mut self, offset: usize, value: isize) {
+        self.cells[offset] = value;
+    }
+}
+
+/// Keys pressed on the host, waiting for the program. Offset 0 reads how
+/// many are waiting and offset 1 takes the next one, or `-1` if there are
+/// none. Writing anywhere clears the buffer.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Keyboard {
+    keys: VecDeque<isize>,
+}
+
+impl Keyboard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn press(&mut self, key: isize) {
+        self.keys.push_back(key);
+    }
+}
+
+impl Device for Keyboard {
+    fn len(&self) -> usize {
+        2
+    }
+
+    fn read(&mut self, offset: usize) -> isize {
+        match offset {
+            0 => self.keys.len() as isize,
+            _ => self.keys.pop_front().unwrap_or(-1),
+        }
+    }
+
+    fn write(&mut self, _: usize, _: isize) {
+        self.keys.clear();
+    }
+}
+
+/// Reads the milliseconds since it was created or last written to.
+#[derive(Clone, Copy, Debug)]
+pub struct Timer {
+    start: Instant,
+}
+
+impl Timer {
+    pub fn new() -> Self {
+        Self { start: Instant::now() }
+    }
+}
+
+impl Default for Timer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Device for Timer {
+    fn len(&self) -> usize {
+        1
+    }
+
+    fn read(&mut self, _: usize) -> isize {
+        self.start.elapsed().as_millis() as isize
+    }
+
+    fn write(&mut self, _: usize, _: isize) {
+        self.start = Instant::now();
+    }
+}
+
+/// Collects characters written to it as text. Reads give the number of
+/// characters written so far.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Console {
+    text: String,
+}
+
+impl Console {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Removes and returns everything written so far.
+    pub fn take_text(&mut self) -> String {
+        std::mem::take(&mut self.text)
+    }
+}
+
+impl Device for Console {
+    fn len(&self) -> usize {
+        1
+    }
+
+    fn read(&mut self, _: usize) -> isize {
+        self.text.chars().count() as isize
+    }
+
+    fn write(&mut self, _: usize, value: isize) {
+        self.text.push(super::ascii::to_char(value).unwrap_or('\u{fffd}'));
+    }
+}
+
+#[derive(Clone)]
+struct Mapping<N> {
+    start: usize,
+    end: usize,
+    device: Arc<Mutex<dyn Device<N>>>,
+}
+
+/// The devices mapped into a machine, shared between its clones.
+#[derive(Clone)]
+pub(crate) struct Devices<N> {
+    mappings: Vec<Mapping<N>>,
+}
+
+impl<N> Devices<N> {
+    pub(crate) fn new() -> Self {
+        Self { mappings: Vec::new() }
+    }
+
+    /// Maps `device` from `start`. Panics if it overlaps another device.
+    pub(crate) fn insert(&mut self, start: usize, device: Arc<Mutex<dyn Device<N>>>) {
+        let end = start + device.lock().unwrap().len();
+        let overlaps = self.mappings.iter().any(|m| start < m.end && m.start < end);
+        assert!(!overlaps, "device at {}..{} overlaps another device", start, end);
+
+        self.mappings.push(Mapping { start, end, device });
+    }
+
+    /// The device mapped at `address`, and the offset into it.
+    #[inline]
+    pub(crate) fn get(&self, address: usize) -> Option<(&Mutex<dyn Device<N>>, usize)> {
+        if self.mappings.is_empty() {
+            return None;
+        }
+
+        self.mappings
+            .iter()
+            .find(|m| m.start <= address && address < m.end)
+            .map(|m| (&*m.device, address - m.start))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::{asm, empty, IntcodeMachine};
+
+    #[test]
+    fn draw_screen() {
+        // Draws a 4x3 box with tile 1 and puts a ball, tile 4, inside it.
+        let program = asm::assemble(
+            "
+        arel imm(1000)
+loop:   add imm(1), imm(0), rel(0)
+        add imm(1), imm(0), rel(8)
+        arel imm(1)
+        add pos(i), imm(1), pos(i)
+        lt pos(i), imm(4), pos(t)
+        jit pos(t), imm(loop)
+        add imm(1), imm(0), pos(1004)
+        add imm(1), imm(0), pos(1007)
+        add imm(4), imm(0), pos(1005)
+        halt
+i:      .data 0
+t:      .data 0
+",
+        )
+        .unwrap();
+
+        let screen = Arc::new(Mutex::new(Framebuffer::new(4, 3)));
+        let mut machine =
+            IntcodeMachine::new(&program, empty(), ()).with_device(1000, screen.clone());
+        machine.run().unwrap();
+
+        let glyph = |tile| match tile {
+            1 => '#',
+            4 => 'o',
+            _ => ' ',
+        };
+        assert_eq!(screen.lock().unwrap().render(glyph), "####\n#o #\n####\n");
+        assert_eq!(machine.data().len(), program.len());
+    }
+
+    #[test]
+    fn keyboard_to_console() {
+        // Copies keys to the console until there are none left, then outputs
+        // how many characters the console has.
+        let program = asm::assemble(
+            "
+loop:   add pos(1101), imm(0), pos(k)
+        eq pos(k), imm(-1), pos(t)
+        jit pos(t), imm(done)
+        add pos(k), imm(0), pos(1200)
+        jit imm(1), imm(loop)
+done:   output pos(1200)
+        halt
+k:      .data 0
+t:      .data 0
+",
+        )
+        .unwrap();
+
+        let keyboard = Arc::new(Mutex::new(Keyboard::new()));
+        let console = Arc::new(Mutex::new(Console::new()));
+        keyboard.lock().unwrap().press(104);
+        keyboard.lock().unwrap().press(105);
+
+        let mut machine = IntcodeMachine::new(&program, empty(), Vec::new())
+            .with_device(1100, keyboard.clone())
+            .with_device(1200, console.clone());
+        machine.run().unwrap();
+
+        assert_eq!(machine.output_mut(), &[2]);
+        assert_eq!(console.lock().unwrap().text(), "hi");
+        assert_eq!(keyboard.lock().unwrap().read(0), 0);
+    }
+}
@@ -0,0 +1,156 @@
+//! Turns a program image back into a readable listing.
+//!
+//! The image is swept linearly: every address that decodes to a valid
+//! instruction is printed as one, everything else is emitted as `.data`, with
+//! long runs of a repeated word collapsed into `.fill count, value`.
+
+use super::{Instruction, Instructions};
+use std::fmt;
+
+/// Runs of at least this many identical words are printed as `.fill`.
+const FILL_THRESHOLD: usize = 8;
+/// Maximum number of words on a single `.data` line.
+const DATA_PER_LINE: usize = 8;
+
+#[derive(Clone, Debug)]
+pub enum Line {
+    Instruction { address: usize, words: Vec<isize>, instruction: Instructions },
+    Data { address: usize, words: Vec<isize> },
+    Fill { address: usize, count: usize, value: isize },
+}
+
+impl Line {
+    pub fn address(&self) -> usize {
+        match self {
+            Line::Instruction { address, .. }
+            | Line::Data { address, .. }
+            | Line::Fill { address, .. } => *address,
+        }
+    }
+
+    /// Number of words of the image covered by this line.
+    pub fn len(&self) -> usize {
+        match self {
+            Line::Instruction { words, .. } | Line::Data { words, .. } => words.len(),
+            Line::Fill { count, .. } => *count,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Line::Instruction { address, words, instruction } => {
+                let raw = words.iter().map(isize::to_string).collect::<Vec<_>>().join(",");
+                write!(f, "{:>5}: {:<36} ; {}", address, instruction.to_string(), raw)
+            }
+            Line::Data { address, words } => {
+                let words = words.iter().map(isize::to_string).collect::<Vec<_>>().join(", ");
+                write!(f, "{:>5}: .data {}", address, words)
+            }
+            Line::Fill { address, count, value } => {
+                write!(f, "{:>5}: .fill {}, {}", address, count, value)
+            }
+        }
+    }
+}
+
+/// Decodes `program` into listing lines in address order.
+pub fn disassemble(program: &[isize]) -> Vec<Line> {
+    disassemble_at(program, 0)
+}
+
+/// Like `disassemble`, for a slice of memory that starts at `origin`.
+pub fn disassemble_at(program: &[isize], origin: usize) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut data_start = 0;
+    let mut address = 0;
+
+    while address < program.len() {
+        match Instructions::decode(&program[address..], address) {
+            Ok(instruction) => {
+                push_data(&mut lines, origin + data_start, &program[data_start..address]);
+
+                let size = instruction.size();
+                let words = program[address..address + size].to_vec();
+                lines.push(Line::Instruction { address: origin + address, words, instruction });
+
+                address += size;
+                data_start = address;
+            }
+            Err(_) => address += 1,
+        }
+    }
+
+    push_data(&mut lines, origin + data_start, &program[data_start..]);
+
+    lines
+}
+
+/// Renders the listing for `program`, one line per instruction or directive.
+pub fn listing(program: &[isize]) -> String {
+    disassemble(program).iter().map(|line| format!("{}\n", line)).collect()
+}
+
+fn push_data(lines: &mut Vec<Line>, mut address: usize, mut words: &[isize]) {
+    while !words.is_empty() {
+        let run = run_length(words);
+
+        let taken = if run >= FILL_THRESHOLD {
+            lines.push(Line::Fill { address, count: run, value: words[0] });
+            run
+        } else {
+            // End the line early if a run long enough for `.fill` starts in it.
+            let end = (1..words.len().min(DATA_PER_LINE))
+                .find(|&i| run_length(&words[i..]) >= FILL_THRESHOLD)
+                .unwrap_or_else(|| words.len().min(DATA_PER_LINE));
+
+            lines.push(Line::Data { address, words: words[..end].to_vec() });
+            end
+        };
+
+        address += taken;
+        words = &words[taken..];
+    }
+}
+
+fn run_length(words: &[isize]) -> usize {
+    words.iter().take_while(|&&n| n == words[0]).count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn listing_with_data() {
+        let mut program = vec![1101, 5, -3, 20, 204, -1, 1, 1, 2, 99, 0, 12345];
+        program.extend(vec![0; 10]);
+
+        let listing = listing(&program);
+        let lines: Vec<_> = listing.lines().map(str::trim_end).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "    0: add imm(5), imm(-3), pos(20)         ; 1101,5,-3,20",
+                "    4: output rel(-1)                       ; 204,-1",
+                "    6: add pos(1), pos(2), pos(99)          ; 1,1,2,99",
+                "   10: .data 0, 12345",
+                "   12: .fill 10, 0",
+            ]
+        );
+    }
+
+    #[test]
+    fn truncated_instruction_is_data() {
+        let lines = disassemble(&[99, 1002, 4]);
+
+        assert_eq!(lines.len(), 2);
+        assert!(matches!(lines[0], Line::Instruction { address: 0, .. }));
+        assert!(matches!(&lines[1], Line::Data { address: 1, words } if words == &[1002, 4]));
+    }
+}
@@ -0,0 +1,29 @@
+//! Helpers for tests that check every engine runs a program the same way.
+
+use super::{Engine, ExitReason, IntcodeError, IntcodeMachine, Source, Tracer, Word};
+
+pub const ENGINES: [Engine; 3] = [Engine::Interpreter, Engine::Cached, Engine::Threaded];
+
+/// Runs the machine `build` returns on each engine, checks they all finish
+/// the same way with the same output, and returns what the interpreter did.
+pub fn run_on_every_engine<R, T, N>(
+    build: impl Fn() -> IntcodeMachine<R, Vec<N>, T, N>,
+) -> (Result<ExitReason, IntcodeError>, Vec<N>)
+where
+    R: Source<N>,
+    T: Tracer<N>,
+    N: Word,
+{
+    let run = |engine| {
+        let mut machine = build().with_engine(engine);
+        let result = machine.run();
+        (result, std::mem::take(machine.output_mut()))
+    };
+
+    let expected = run(Engine::Interpreter);
+    for &engine in &ENGINES[1..] {
+        assert_eq!(run(engine), expected, "{:?}", engine);
+    }
+
+    expected
+}
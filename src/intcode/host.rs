@@ -0,0 +1,234 @@
+//! Calls from Intcode programs into Rust.
+//!
+//! `IntcodeMachine::with_host` adds a `host n, args, dst` instruction under
+//! an opcode of the caller's choosing. It runs the callback registered as
+//! call `n` in a `Host`, which reads its arguments from memory starting at
+//! address `args` and whose result is written to `dst`. Callbacks can also
+//! read and write any other memory, e.g. to take a string or fill a buffer.
+
+use super::custom::{Handler, Machine};
+use super::{address, ascii, Destination, ErrorKind, Operand};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+type Callback = Box<dyn FnMut(&mut Call<'_>) -> Result<isize, ErrorKind> + Send>;
+
+/// The callbacks a program can reach with the host instruction, by number.
+#[derive(Default)]
+pub struct Host {
+    calls: HashMap<isize, Callback>,
+}
+
+impl Host {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `callback` as call `number`, replacing any already there.
+    pub fn with_call<F>(mut self, number: isize, callback: F) -> Self
+    where
+        F: FnMut(&mut Call<'_>) -> Result<isize, ErrorKind> + Send + 'static,
+    {
+        self.calls.insert(number, Box::new(callback));
+        self
+    }
+
+    fn call(&mut self, call: &mut Call<'_>) -> Result<isize, ErrorKind> {
+        let callback =
+            self.calls.get_mut(&call.number).ok_or(ErrorKind::UnknownHostCall(call.number))?;
+
+        callback(call)
+    }
+}
+
+impl fmt::Debug for Host {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut numbers: Vec<_> = self.calls.keys().collect();
+        numbers.sort();
+
+        f.debug_struct("Host").field("calls", &numbers).finish()
+    }
+}
+
+/// A call in progress, giving its callback access to the machine's memory.
+pub struct Call<'a> {
+    number: isize,
+    args: usize,
+    machine: &'a mut dyn Machine,
+}
+
+impl Call<'_> {
+    pub fn number(&self) -> isize {
+        self.number
+    }
+
+    /// The address the call's arguments start at.
+    pub fn args(&self) -> usize {
+        self.args
+    }
+
+    /// The `n`th argument, read from memory after the call's `args` address.
+    pub fn arg(&mut self, n: usize) -> Result<isize, ErrorKind> {
+        self.machine.read(self.args + n)
+    }
+
+    pub fn read(&mut self, address: usize) -> Result<isize, ErrorKind> {
+        self.machine.read(address)
+    }
+
+    pub fn write(&mut self, address: usize, value: isize) -> Result<(), ErrorKind> {
+        self.machine.write(address, value)
+    }
+
+    /// Reads characters starting at `address` up to a zero. Values outside
+    /// the ASCII range come out as U+FFFD.
+    pub fn read_str(&mut self, mut address: usize) -> Result<String, ErrorKind> {
+        let mut text = String::new();
+
+        loop {
+            match self.machine.read(address)? {
+                0 => return Ok(text),
+                value => text.push(ascii::to_char(value).unwrap_or('\u{fffd}')),
+            }
+            address += 1;
+        }
+    }
+
+    /// Writes `bytes` to memory one per cell, starting at `address`.
+    pub fn write_bytes(&mut self, address: usize, bytes: &[u8]) -> Result<(), ErrorKind> {
+        for (i, &byte) in bytes.iter().enumerate() {
+            self.machine.write(address + i, isize::from(byte))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// `host n, args, dst`: runs call `n` of `host`. Clones of the machine
+/// share `host`, since the handler holds on to it.
+pub(crate) fn handler(host: Host) -> Handler {
+    let host = Mutex::new(host);
+
+    Arc::new(move |machine, params| {
+        let call = HostCall {
+            number: params[0].operand()?,
+            args: params[1].operand()?,
+            dst: params[2].destination()?,
+        };
+
+        call.execute(machine, &host)
+    })
+}
+
+struct HostCall {
+    number: Operand,
+    args: Operand,
+    dst: Destination,
+}
+
+impl HostCall {
+    fn execute(&self, machine: &mut dyn Machine, host: &Mutex<Host>) -> Result<(), ErrorKind> {
+        let number = self.number.resolve(machine)?;
+        let args = address(self.args.resolve(machine)?)?;
+        let dst = self.dst.resolve(machine)?;
+
+        let result = host.lock().unwrap().call(&mut Call { number, args, machine })?;
+
+        machine.write(dst, result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::trace::TextTracer;
+    use crate::intcode::{asm, empty, IntcodeError, IntcodeMachine};
+
+    const HOST_OP: usize = 80;
+
+    #[test]
+    fn calls() {
+        // Prints the string at `text` and outputs how long it was, then the
+        // sum of 3 and 4.
+        let mut program = asm::assemble(
+            "
+        add imm(0), imm(0), pos(0)
+        output pos(len)
+        add imm(0), imm(0), pos(4)
+        output pos(sum)
+        halt
+len:    .data 0
+sum:    .data 0
+text:   .data 104, 105, 0
+args:   .data 3, 4
+",
+        )
+        .unwrap();
+
+        // The assembler doesn't know the host instruction, so patch it in.
+        let (len, sum, text, args) = (13, 14, 15, 18);
+        program[0..4].copy_from_slice(&[1180, 0, text, len]);
+        program[6..10].copy_from_slice(&[1180, 1, args, sum]);
+
+        let printed = Arc::new(Mutex::new(Vec::new()));
+        let host = Host::new()
+            .with_call(0, {
+                let printed = printed.clone();
+                move |call| {
+                    let text = call.read_str(call.args())?;
+                    printed.lock().unwrap().push(text.clone());
+                    Ok(text.len() as isize)
+                }
+            })
+            .with_call(1, |call| Ok(call.arg(0)? + call.arg(1)?));
+
+        let mut machine =
+            IntcodeMachine::new(&program, empty(), Vec::new()).with_host(HOST_OP, host);
+        machine.run().unwrap();
+
+        assert_eq!(machine.output_mut(), &[2, 7]);
+        assert_eq!(*printed.lock().unwrap(), vec![String::from("hi")]);
+    }
+
+    #[test]
+    fn unknown_call() {
+        let program = [1180, 5, 0, 0, 99];
+        let mut machine =
+            IntcodeMachine::new(&program, empty(), ()).with_host(HOST_OP, Host::new());
+
+        assert_eq!(machine.run(), Err(IntcodeError::new(0, 1180, ErrorKind::UnknownHostCall(5))));
+    }
+
+    #[test]
+    fn fill_buffer() {
+        // Fills memory from 10 with the callback's bytes.
+        let program = [1180, 0, 10, 5, 99];
+        let host = Host::new().with_call(0, |call| {
+            call.write_bytes(call.args(), b"abc")?;
+            Ok(3)
+        });
+
+        let mut machine = IntcodeMachine::new(&program, empty(), ()).with_host(HOST_OP, host);
+        machine.run().unwrap();
+
+        assert_eq!(machine.data().to_vec()[10..13], [97, 98, 99]);
+        assert_eq!(machine.data().read(5), Ok(3));
+    }
+
+    #[test]
+    fn traced() {
+        let program = [1180, 0, 10, 5, 99];
+        let host = Host::new().with_call(0, |_| Ok(42));
+        let mut tracer = TextTracer::new(vec![]);
+
+        let mut machine = IntcodeMachine::new(&program, empty(), ())
+            .with_host(HOST_OP, host)
+            .with_tracer(&mut tracer);
+        machine.run().unwrap();
+        assert_eq!(machine.data().read(5), Ok(42));
+
+        let trace = String::from_utf8(tracer.into_inner().unwrap()).unwrap();
+        assert!(trace.starts_with("    0: host imm(0), imm(10), pos(5)\n"), "{}", trace);
+    }
+}
@@ -0,0 +1,224 @@
+use super::{ErrorKind, Word};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::ops::Index;
+
+/// How far contiguous memory grows before a write past its end switches it
+/// over to a hash map, 128 MiB worth of cells.
+const MAX_DENSE_LEN: usize = 1 << 24;
+
+/// Backing store for an `IntcodeMachine`.
+///
+/// Memory grows on demand when written past its end and reads as zero beyond
+/// it. An optional limit turns any access at or above it into
+/// `ErrorKind::AddressOutOfBounds`.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Memory<N = isize> {
+    cells: Cells<N>,
+    len: usize,
+    limit: Option<usize>,
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+enum Cells<N> {
+    Dense(Vec<N>),
+    Sparse(HashMap<usize, N>),
+}
+
+impl<N: Word> Memory<N> {
+    /// Contiguous memory initialized with `program`. It becomes sparse if
+    /// written far past its end, rather than growing without bound.
+    pub fn new(program: &[N]) -> Self {
+        Self { cells: Cells::Dense(program.to_vec()), len: program.len(), limit: None }
+    }
+
+    /// Hash map backed memory for programs that touch very high addresses.
+    pub fn sparse(program: &[N]) -> Self {
+        let cells = program.iter().cloned().enumerate().filter(|(_, n)| !n.is_zero()).collect();
+
+        Self { cells: Cells::Sparse(cells), len: program.len(), limit: None }
+    }
+
+    /// Sparse memory of length `len` holding `cells`, as produced by
+    /// `nonzero_cells`.
+    pub(crate) fn sparse_from_cells(len: usize, cells: &[(usize, N)]) -> Self {
+        let len = cells.iter().map(|(address, _)| address + 1).fold(len, usize::max);
+        let cells = cells.iter().filter(|(_, n)| !n.is_zero()).cloned().collect();
+
+        Self { cells: Cells::Sparse(cells), len, limit: None }
+    }
+
+    /// Restricts accesses to addresses below `limit`.
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    /// One past the highest address that has been loaded or written.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_sparse(&self) -> bool {
+        match self.cells {
+            Cells::Dense(_) => false,
+            Cells::Sparse(_) => true,
+        }
+    }
+
+    #[inline]
+    pub fn read(&self, address: usize) -> Result<N, ErrorKind> {
+        self.check(address)?;
+
+        Ok(self[address].clone())
+    }
+
+    #[inline]
+    pub fn write(&mut self, address: usize, value: N) -> Result<(), ErrorKind> {
+        self.check(address)?;
+
+        if let Cells::Dense(cells) = &mut self.cells {
+            if address >= cells.len().max(MAX_DENSE_LEN) {
+                let cells = cells.drain(..).enumerate().filter(|(_, n)| !n.is_zero()).collect();
+                self.cells = Cells::Sparse(cells);
+            }
+        }
+
+        match &mut self.cells {
+            Cells::Dense(cells) => {
+                if address >= cells.len() {
+                    cells.resize(address + 1, N::ZERO.clone());
+                }
+
+                cells[address] = value;
+            }
+            Cells::Sparse(cells) if value.is_zero() => {
+                cells.remove(&address);
+            }
+            Cells::Sparse(cells) => {
+                cells.insert(address, value);
+            }
+        }
+
+        self.len = self.len.max(address + 1);
+
+        Ok(())
+    }
+
+    /// Reads the four words starting at `address`, enough for the largest
+    /// instruction and its parameters.
+    pub(crate) fn fetch(&self, address: usize) -> Result<[N; 4], ErrorKind> {
+        self.check(address)?;
+
+        Ok([0, 1, 2, 3].map(|i| self[address + i].clone()))
+    }
+
+    /// Copies the first `len()` cells out into a contiguous image.
+    pub fn to_vec(&self) -> Vec<N> {
+        match &self.cells {
+            Cells::Dense(cells) => cells.clone(),
+            Cells::Sparse(_) => (0..self.len).map(|i| self[i].clone()).collect(),
+        }
+    }
+
+    /// Every non-zero cell as `(address, value)`, in address order.
+    pub fn nonzero_cells(&self) -> Vec<(usize, N)> {
+        let mut cells: Vec<_> = match &self.cells {
+            Cells::Dense(cells) => {
+                cells.iter().cloned().enumerate().filter(|(_, n)| !n.is_zero()).collect()
+            }
+            Cells::Sparse(cells) => cells.iter().map(|(a, n)| (*a, n.clone())).collect(),
+        };
+
+        cells.sort_unstable();
+        cells
+    }
+
+    #[inline]
+    fn check(&self, address: usize) -> Result<(), ErrorKind> {
+        match self.limit {
+            Some(limit) if address >= limit => {
+                let address = isize::try_from(address).unwrap_or(isize::MAX);
+                Err(ErrorKind::AddressOutOfBounds(address))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<N: Word> Index<usize> for Memory<N> {
+    type Output = N;
+
+    #[inline]
+    fn index(&self, address: usize) -> &N {
+        let cell = match &self.cells {
+            Cells::Dense(cells) => cells.get(address),
+            Cells::Sparse(cells) => cells.get(&address),
+        };
+
+        cell.unwrap_or(N::ZERO)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grows_on_write() {
+        let mut memory: Memory = Memory::new(&[1, 2, 3]);
+        assert_eq!(memory.read(10), Ok(0));
+        assert_eq!(memory.len(), 3);
+
+        memory.write(10, 7).unwrap();
+        assert_eq!(memory.len(), 11);
+        assert_eq!(memory[10], 7);
+        assert_eq!(memory.to_vec(), vec![1, 2, 3, 0, 0, 0, 0, 0, 0, 0, 7]);
+    }
+
+    #[test]
+    fn sparse_high_addresses() {
+        let mut memory: Memory = Memory::sparse(&[1, 0, 3]);
+        memory.write(1 << 40, 5).unwrap();
+
+        assert_eq!(memory.read(1 << 40), Ok(5));
+        assert_eq!(memory.read((1 << 40) - 1), Ok(0));
+        assert_eq!(memory[2], 3);
+        assert_eq!(memory.len(), (1 << 40) + 1);
+    }
+
+    #[test]
+    fn dense_becomes_sparse() {
+        let mut memory: Memory = Memory::new(&[1, 2, 3]);
+        memory.write(1 << 40, 5).unwrap();
+
+        assert!(memory.is_sparse());
+        assert_eq!(memory.read(1 << 40), Ok(5));
+        assert_eq!(memory[2], 3);
+        assert_eq!(memory.len(), (1 << 40) + 1);
+
+        let mut memory: Memory = Memory::new(&[]);
+        memory.write(isize::MAX as usize, 1).unwrap();
+        assert_eq!(memory.read(isize::MAX as usize), Ok(1));
+    }
+
+    #[test]
+    fn limit() {
+        let mut memory: Memory = Memory::new(&[1, 2, 3]).with_limit(8);
+
+        assert_eq!(memory.write(7, 1), Ok(()));
+        assert_eq!(memory.write(8, 1), Err(ErrorKind::AddressOutOfBounds(8)));
+        assert_eq!(memory.read(100), Err(ErrorKind::AddressOutOfBounds(100)));
+        assert_eq!(memory.read(usize::MAX), Err(ErrorKind::AddressOutOfBounds(isize::MAX)));
+    }
+}
@@ -0,0 +1,282 @@
+//! A simulated network of machines that exchange packets, as in day 23.
+//!
+//! Each machine is booted with its address as its first input. It sends a
+//! packet by outputting the destination address followed by `x` and `y`,
+//! and receives one as two inputs. Reading from an empty queue gives `-1`.
+//!
+//! Machines are run one at a time in address order, each until it asks for
+//! input again after being told its queue is empty, so a run is entirely
+//! deterministic. A round in which nothing is sent and every queue stays
+//! empty leaves the network idle. A NAT, if there is one, holds the last
+//! packet sent to its address and wakes an idle network by passing it on to
+//! address 0.
+
+use super::{Event, IntcodeError, IntcodeMachine, Received, Source};
+use std::collections::VecDeque;
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Packet {
+    pub from: usize,
+    pub to: usize,
+    pub x: isize,
+    pub y: isize,
+}
+
+/// What happened during a call to `Network::step`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    /// Some machine sent a packet or has some queued.
+    Busy,
+    /// The network went idle and the NAT woke it with this packet.
+    Woken(Packet),
+    /// The network went idle and there's nothing to wake it, so it will
+    /// stay idle until a packet is sent with `Network::send`.
+    Idle,
+}
+
+#[derive(Debug)]
+pub enum NetworkError {
+    Machine {
+        address: usize,
+        error: IntcodeError,
+    },
+    UnknownAddress(Packet),
+    /// A machine output a negative address, so no packet could be built.
+    InvalidAddress {
+        from: usize,
+        to: isize,
+    },
+}
+
+impl fmt::Display for NetworkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NetworkError::Machine { address, error } => write!(f, "machine {}: {}", address, error),
+            NetworkError::UnknownAddress(p) => {
+                write!(f, "machine {} sent a packet to unknown address {}", p.from, p.to)
+            }
+            NetworkError::InvalidAddress { from, to } => {
+                write!(f, "machine {} sent a packet to invalid address {}", from, to)
+            }
+        }
+    }
+}
+
+impl std::error::Error for NetworkError {}
+
+/// A machine's input queue. It answers `-1` the first time it's found empty
+/// in a round and blocks after that, which hands control to the next
+/// machine.
+#[derive(Clone, Debug, Default)]
+struct Nic {
+    queue: VecDeque<isize>,
+    polled_empty: bool,
+}
+
+impl Source for Nic {
+    fn recv(&mut self) -> Received {
+        match self.queue.pop_front() {
+            Some(value) => Received::Value(value),
+            None if self.polled_empty => Received::WouldBlock,
+            None => {
+                self.polled_empty = true;
+                Received::Value(-1)
+            }
+        }
+    }
+}
+
+struct Host {
+    machine: IntcodeMachine<Nic, ()>,
+    /// Output of a packet that hasn't been sent in full yet.
+    sending: Vec<isize>,
+}
+
+pub struct Network {
+    hosts: Vec<Host>,
+    nat: Option<usize>,
+    nat_packet: Option<Packet>,
+    log: Vec<Packet>,
+}
+
+impl Network {
+    /// Boots `size` copies of `program` at addresses `0..size`.
+    pub fn new(program: &[isize], size: usize) -> Self {
+        let hosts = (0..size)
+            .map(|address| {
+                let nic = Nic { queue: vec![address as isize].into(), polled_empty: false };
+                let machine = IntcodeMachine::new(program, nic, ());
+
+                Host { machine, sending: Vec::new() }
+            })
+            .collect();
+
+        Self { hosts, nat: None, nat_packet: None, log: Vec::new() }
+    }
+
+    /// Puts a NAT at `address`. Panics if `address` is one of the
+    /// machines'.
+    pub fn with_nat(mut self, address: usize) -> Self {
+        assert!(address >= self.hosts.len(), "NAT address {} is taken by a machine", address);
+
+        self.nat = Some(address);
+        self
+    }
+
+    /// Queues a packet as if it had been sent from `packet.from`.
+    pub fn send(&mut self, packet: Packet) -> Result<(), NetworkError> {
+        self.log.push(packet);
+
+        if Some(packet.to) == self.nat {
+            self.nat_packet = Some(packet);
+            return Ok(());
+        }
+
+        let host = self.hosts.get_mut(packet.to).ok_or(NetworkError::UnknownAddress(packet))?;
+        host.machine.input.queue.extend(&[packet.x, packet.y]);
+
+        Ok(())
+    }
+
+    /// Runs every machine once in address order.
+    pub fn step(&mut self) -> Result<Status, NetworkError> {
+        let sent_before = self.log.len();
+
+        for address in 0..self.hosts.len() {
+            self.hosts[address].machine.input.polled_empty = false;
+
+            while let Some(packet) = self.run_host(address)? {
+                self.send(packet)?;
+            }
+        }
+
+        let busy = self.log.len() > sent_before
+            || self.hosts.iter().any(|host| !host.machine.input.queue.is_empty());
+
+        match self.nat_packet {
+            _ if busy => Ok(Status::Busy),
+            Some(packet) => {
+                let packet = Packet { from: self.nat.unwrap(), to: 0, ..packet };
+                self.send(packet)?;
+                Ok(Status::Woken(packet))
+            }
+            None => Ok(Status::Idle),
+        }
+    }
+
+    /// Runs the machine at `address` until it sends a packet or blocks.
+    fn run_host(&mut self, address: usize) -> Result<Option<Packet>, NetworkError> {
+        let host = &mut self.hosts[address];
+
+        loop {
+            match host.machine.run_until_event() {
+                Ok(Event::Output(value)) => {
+                    host.sending.push(value);
+
+                    if let [to, x, y] = host.sending[..] {
+                        host.sending.clear();
+
+                        if to < 0 {
+                            return Err(NetworkError::InvalidAddress { from: address, to });
+                        }
+
+                        return Ok(Some(Packet { from: address, to: to as usize, x, y }));
+                    }
+                }
+                Ok(Event::NeedsInput) | Ok(Event::Halted) => return Ok(None),
+                Err(error) => return Err(NetworkError::Machine { address, error }),
+            }
+        }
+    }
+
+    /// Every packet sent so far, including those from the NAT and `send`.
+    pub fn log(&self) -> &[Packet] {
+        &self.log
+    }
+
+    /// The packet the NAT would wake the network with.
+    pub fn nat_packet(&self) -> Option<Packet> {
+        self.nat_packet
+    }
+
+    pub fn len(&self) -> usize {
+        self.hosts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hosts.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::asm;
+
+    /// Passes every packet it gets on to the next address with `x`
+    /// incremented, with the last machine sending to the NAT.
+    const RELAY: &str = "
+        input pos(addr)
+        add pos(addr), imm(1), pos(dest)
+        eq pos(dest), imm(3), pos(t)
+        jif pos(t), imm(loop)
+        add imm(255), imm(0), pos(dest)
+loop:   input pos(x)
+        eq pos(x), imm(-1), pos(t)
+        jit pos(t), imm(loop)
+        input pos(y)
+        add pos(x), imm(1), pos(x)
+        output pos(dest)
+        output pos(x)
+        output pos(y)
+        jit imm(1), imm(loop)
+addr:   .data 0
+dest:   .data 0
+x:      .data 0
+y:      .data 0
+t:      .data 0
+";
+
+    #[test]
+    fn relay_through_nat() {
+        let program = asm::assemble(RELAY).unwrap();
+        let mut network = Network::new(&program, 3).with_nat(255);
+
+        assert_eq!(network.step().unwrap(), Status::Idle);
+
+        // Packets sent to a higher address are delivered in the same step.
+        network.send(Packet { from: 99, to: 0, x: 0, y: 7 }).unwrap();
+        assert_eq!(network.step().unwrap(), Status::Busy);
+
+        let nat = Packet { from: 255, to: 0, x: 3, y: 7 };
+        assert_eq!(network.step().unwrap(), Status::Woken(nat));
+
+        let hops: Vec<_> = network.log().iter().map(|p| (p.from, p.to, p.x)).collect();
+        assert_eq!(hops, vec![(99, 0, 0), (0, 1, 1), (1, 2, 2), (2, 255, 3), (255, 0, 3)]);
+
+        assert_eq!(network.step().unwrap(), Status::Busy);
+        assert_eq!(network.step().unwrap(), Status::Woken(Packet { x: 6, ..nat }));
+    }
+
+    #[test]
+    fn unknown_address() {
+        let program = asm::assemble(RELAY).unwrap();
+        let mut network = Network::new(&program, 3);
+
+        network.send(Packet { from: 99, to: 2, x: 0, y: 0 }).unwrap();
+        assert!(matches!(
+            network.step(),
+            Err(NetworkError::UnknownAddress(Packet { from: 2, to: 255, .. }))
+        ));
+
+        let mut network = Network::new(&[104, -1, 104, 0, 104, 0, 99], 1);
+        assert!(matches!(network.step(), Err(NetworkError::InvalidAddress { from: 0, to: -1 })));
+    }
+
+    #[test]
+    #[should_panic(expected = "NAT address 1 is taken by a machine")]
+    fn nat_on_machine_address() {
+        Network::new(&[99], 2).with_nat(1);
+    }
+}
@@ -0,0 +1,241 @@
+//! Instruction-level profiling.
+//!
+//! `Profiler` is a `Tracer` that counts how often each address is executed,
+//! how often each kind of instruction runs, how often each memory cell is
+//! read and written, and which backwards jumps (loop back-edges) are taken.
+//! Once the machine is done, `report` renders the hot spots as a table and
+//! `write_json` dumps every counter so runs can be compared.
+
+use super::trace::JsonStr;
+use super::{Instructions, Tracer};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+use std::io::{self, Write};
+
+#[derive(Clone, Debug, Default)]
+pub struct Profiler {
+    instructions: u64,
+    /// Execution count per address, with the instruction first seen there.
+    addresses: HashMap<usize, (u64, Instructions)>,
+    opcodes: BTreeMap<&'static str, u64>,
+    reads: HashMap<usize, u64>,
+    writes: HashMap<usize, u64>,
+    back_edges: HashMap<(usize, usize), u64>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Total number of instructions executed.
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    pub fn executions(&self, address: usize) -> u64 {
+        self.addresses.get(&address).map_or(0, |&(count, _)| count)
+    }
+
+    pub fn reads(&self, address: usize) -> u64 {
+        self.reads.get(&address).copied().unwrap_or(0)
+    }
+
+    pub fn writes(&self, address: usize) -> u64 {
+        self.writes.get(&address).copied().unwrap_or(0)
+    }
+
+    /// Addresses ordered from most to least executed, ties by address.
+    pub fn hottest(&self) -> Vec<(usize, u64, &Instructions)> {
+        let mut hottest: Vec<_> = self
+            .addresses
+            .iter()
+            .map(|(&address, (count, instruction))| (address, *count, instruction))
+            .collect();
+        hottest.sort_by_key(|&(address, count, _)| (std::cmp::Reverse(count), address));
+        hottest
+    }
+
+    /// Backwards jumps taken as `(from, to, count)`, most taken first.
+    pub fn back_edges(&self) -> Vec<(usize, usize, u64)> {
+        let mut edges: Vec<_> =
+            self.back_edges.iter().map(|(&(from, to), &count)| (from, to, count)).collect();
+        edges.sort_by_key(|&(from, to, count)| (std::cmp::Reverse(count), from, to));
+        edges
+    }
+
+    /// Renders a table of the `top` hottest addresses and memory cells, the
+    /// instruction mix and the loop back-edges.
+    pub fn report(&self, top: usize) -> String {
+        let mut report = String::new();
+        let percent = |count: u64| 100.0 * count as f64 / self.instructions.max(1) as f64;
+
+        let _ = writeln!(report, "instructions executed: {}", self.instructions);
+
+        let _ = writeln!(report, "\nhottest addresses:");
+        let _ = writeln!(report, "{:>12} {:>7}  {:>7}  instruction", "count", "%", "address");
+        for (address, count, instruction) in self.hottest().into_iter().take(top) {
+            let _ = writeln!(
+                report,
+                "{:>12} {:>6.2}%  {:>7}  {}",
+                count,
+                percent(count),
+                address,
+                instruction
+            );
+        }
+
+        let _ = writeln!(report, "\ninstruction mix:");
+        let mut opcodes: Vec<_> = self.opcodes.iter().collect();
+        opcodes.sort_by_key(|&(_, &count)| std::cmp::Reverse(count));
+        for (mnemonic, &count) in opcodes {
+            let _ = writeln!(report, "{:>12} {:>6.2}%  {}", count, percent(count), mnemonic);
+        }
+
+        let _ = writeln!(report, "\nhottest memory cells:");
+        let _ = writeln!(report, "{:>12} {:>12}  address", "reads", "writes");
+        let mut cells: Vec<_> = self.reads.keys().chain(self.writes.keys()).copied().collect();
+        cells.sort_unstable();
+        cells.dedup();
+        cells.sort_by_key(|&a| (std::cmp::Reverse(self.reads(a) + self.writes(a)), a));
+        for address in cells.into_iter().take(top) {
+            let _ = writeln!(
+                report,
+                "{:>12} {:>12}  {}",
+                self.reads(address),
+                self.writes(address),
+                address
+            );
+        }
+
+        let _ = writeln!(report, "\nloop back-edges:");
+        let _ = writeln!(report, "{:>12}  from -> to", "count");
+        for (from, to, count) in self.back_edges().into_iter().take(top) {
+            let _ = writeln!(report, "{:>12}  {} -> {}", count, from, to);
+        }
+
+        report
+    }
+
+    /// Writes every counter as a single JSON object, with lists sorted by
+    /// address so profiles of different runs diff cleanly.
+    pub fn write_json<W: Write>(&self, mut out: W) -> io::Result<()> {
+        let mut addresses: Vec<_> = self.addresses.iter().collect();
+        addresses.sort_by_key(|&(&address, _)| address);
+        let addresses: Vec<_> = addresses
+            .into_iter()
+            .map(|(address, (count, instruction))| {
+                format!(
+                    r#"{{"address":{},"count":{},"instruction":"{}"}}"#,
+                    address,
+                    count,
+                    JsonStr(instruction)
+                )
+            })
+            .collect();
+
+        let opcodes: Vec<_> = self
+            .opcodes
+            .iter()
+            .map(|(mnemonic, count)| format!(r#""{}":{}"#, JsonStr(mnemonic), count))
+            .collect();
+
+        let cells = |counts: &HashMap<usize, u64>| {
+            let mut counts: Vec<_> = counts.iter().collect();
+            counts.sort_unstable();
+            counts
+                .into_iter()
+                .map(|(address, count)| format!(r#"{{"address":{},"count":{}}}"#, address, count))
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+
+        let mut back_edges: Vec<_> = self.back_edges.iter().collect();
+        back_edges.sort_unstable();
+        let back_edges: Vec<_> = back_edges
+            .into_iter()
+            .map(|((from, to), count)| {
+                format!(r#"{{"from":{},"to":{},"count":{}}}"#, from, to, count)
+            })
+            .collect();
+
+        writeln!(
+            out,
+            r#"{{"instructions":{},"addresses":[{}],"opcodes":{{{}}},"reads":[{}],"writes":[{}],"back_edges":[{}]}}"#,
+            self.instructions,
+            addresses.join(","),
+            opcodes.join(","),
+            cells(&self.reads),
+            cells(&self.writes),
+            back_edges.join(",")
+        )
+    }
+}
+
+impl Tracer for Profiler {
+    fn decode(&mut self, ip: usize, instruction: &Instructions) {
+        self.instructions += 1;
+        self.addresses.entry(ip).or_insert_with(|| (0, *instruction)).0 += 1;
+        *self.opcodes.entry(instruction.mnemonic()).or_insert(0) += 1;
+    }
+
+    fn read(&mut self, address: usize, _value: isize) {
+        *self.reads.entry(address).or_insert(0) += 1;
+    }
+
+    fn write(&mut self, address: usize, _value: isize) {
+        *self.writes.entry(address).or_insert(0) += 1;
+    }
+
+    fn jump(&mut self, from: usize, to: usize) {
+        if to <= from {
+            *self.back_edges.entry((from, to)).or_insert(0) += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::asm::assemble;
+    use crate::intcode::{empty, IntcodeMachine};
+
+    #[test]
+    fn counts_loop() {
+        let program = assemble(
+            "
+                    add imm(0), imm(3), pos(n)
+            loop:   add pos(n), imm(-1), pos(n)
+                    jit pos(n), imm(loop)
+                    halt
+            n:      .data 0
+            ",
+        )
+        .unwrap();
+
+        let mut profiler = Profiler::new();
+        let mut machine =
+            IntcodeMachine::new(&program, empty(), Vec::new()).with_tracer(&mut profiler);
+        machine.run().unwrap();
+
+        assert_eq!(profiler.instructions(), 8);
+        assert_eq!(profiler.executions(4), 3);
+        assert_eq!(profiler.executions(8), 3);
+        assert_eq!(profiler.reads(12), 6);
+        assert_eq!(profiler.writes(12), 4);
+        assert_eq!(profiler.back_edges(), vec![(8, 4, 2)]);
+        assert_eq!(profiler.hottest()[0].0, 4);
+
+        let report = profiler.report(2);
+        assert!(report.starts_with("instructions executed: 8\n"), "{}", report);
+        assert!(report.contains("           3  37.50%        4  add pos(12), imm(-1), pos(12)\n"));
+        assert!(report.contains("           2  8 -> 4\n"));
+
+        let mut json = Vec::new();
+        profiler.write_json(&mut json).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        assert!(json.starts_with(r#"{"instructions":8,"addresses":[{"address":0,"count":1,"#));
+        assert!(json.contains(r#""opcodes":{"add":4,"halt":1,"jit":3}"#), "{}", json);
+        assert!(json.ends_with("\"back_edges\":[{\"from\":8,\"to\":4,\"count\":2}]}\n"));
+    }
+}
@@ -0,0 +1,412 @@
+//! Saving `MachineState`s to disk and loading them back.
+//!
+//! Both encodings begin with a version number so newer builds can keep
+//! reading older snapshots. The binary format is little-endian throughout:
+//!
+//! ```text
+//! magic          4 bytes  b"ICSS"
+//! version        u16      currently 1
+//! flags          u8       bit 0: halted, bit 1: sparse memory, bit 2: has limit
+//! ip             u64
+//! relative_base  i64
+//! limit          u64      only present when flag bit 2 is set
+//! pending        u64 count, followed by that many i64 inputs
+//! memory length  u64
+//! dense memory   `length` i64 words
+//! sparse memory  u64 count, followed by that many (u64 address, i64 value)
+//! ```
+//!
+//! The text format has one `key value` pair per line, in any order after the
+//! header. Blank lines and anything after a `#` are ignored. Exactly one of
+//! `memory` (dense) or `sparse` (length, then `address=value` cells) must be
+//! present, while `limit` and `pending` are optional:
+//!
+//! ```text
+//! intcode-snapshot 1
+//! ip 2
+//! relative_base 0
+//! halted false
+//! pending 7,8
+//! memory 3,9,4,9,99,0,0,0,0,5
+//! ```
+
+use super::{MachineState, Memory};
+use std::fmt::{self, Write as _};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"ICSS";
+const VERSION: u16 = 1;
+const TEXT_HEADER: &str = "intcode-snapshot";
+
+const HALTED: u8 = 1;
+const SPARSE: u8 = 1 << 1;
+const LIMITED: u8 = 1 << 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SnapshotFormat {
+    Binary,
+    Text,
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    UnsupportedVersion(u64),
+    Malformed(String),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(e) => write!(f, "{}", e),
+            SnapshotError::UnsupportedVersion(v) => write!(f, "unsupported snapshot version {}", v),
+            SnapshotError::Malformed(reason) => write!(f, "malformed snapshot: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<io::Error> for SnapshotError {
+    fn from(e: io::Error) -> Self {
+        SnapshotError::Io(e)
+    }
+}
+
+impl MachineState {
+    pub fn save<P: AsRef<Path>>(&self, path: P, format: SnapshotFormat) -> io::Result<()> {
+        match format {
+            SnapshotFormat::Binary => {
+                self.write_binary(io::BufWriter::new(fs::File::create(path)?))
+            }
+            SnapshotFormat::Text => fs::write(path, self.to_text()),
+        }
+    }
+
+    /// Loads a snapshot, detecting whether it was saved as binary or text.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, SnapshotError> {
+        let bytes = fs::read(path)?;
+
+        if bytes.starts_with(MAGIC) {
+            Self::read_binary(&bytes[..])
+        } else {
+            let text = String::from_utf8(bytes)
+                .map_err(|_| SnapshotError::Malformed(String::from("not a snapshot file")))?;
+            Self::from_text(&text)
+        }
+    }
+
+    pub fn write_binary<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let mut flags = 0;
+        if self.halted {
+            flags |= HALTED;
+        }
+        if self.memory.is_sparse() {
+            flags |= SPARSE;
+        }
+        if self.memory.limit().is_some() {
+            flags |= LIMITED;
+        }
+
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&[flags])?;
+        write_u64(&mut writer, self.ip as u64)?;
+        write_i64(&mut writer, self.relative_base as i64)?;
+
+        if let Some(limit) = self.memory.limit() {
+            write_u64(&mut writer, limit as u64)?;
+        }
+
+        write_u64(&mut writer, self.pending.len() as u64)?;
+        for &n in &self.pending {
+            write_i64(&mut writer, n as i64)?;
+        }
+
+        write_u64(&mut writer, self.memory.len() as u64)?;
+        if self.memory.is_sparse() {
+            let cells = self.memory.nonzero_cells();
+            write_u64(&mut writer, cells.len() as u64)?;
+
+            for (address, n) in cells {
+                write_u64(&mut writer, address as u64)?;
+                write_i64(&mut writer, n as i64)?;
+            }
+        } else {
+            for n in self.memory.to_vec() {
+                write_i64(&mut writer, n as i64)?;
+            }
+        }
+
+        writer.flush()
+    }
+
+    pub fn read_binary<R: Read>(mut reader: R) -> Result<Self, SnapshotError> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(SnapshotError::Malformed(String::from("bad magic number")));
+        }
+
+        let mut version = [0; 2];
+        reader.read_exact(&mut version)?;
+        let version = u16::from_le_bytes(version);
+        if version != VERSION {
+            return Err(SnapshotError::UnsupportedVersion(u64::from(version)));
+        }
+
+        let mut flags = [0];
+        reader.read_exact(&mut flags)?;
+        let flags = flags[0];
+
+        let ip = read_usize(&mut reader)?;
+        let relative_base = read_i64(&mut reader)? as isize;
+        let limit = if flags & LIMITED != 0 { Some(read_usize(&mut reader)?) } else { None };
+
+        let pending = (0..read_u64(&mut reader)?)
+            .map(|_| Ok(read_i64(&mut reader)? as isize))
+            .collect::<Result<_, SnapshotError>>()?;
+
+        let len = read_usize(&mut reader)?;
+        let memory = if flags & SPARSE != 0 {
+            let cells = (0..read_u64(&mut reader)?)
+                .map(|_| Ok((read_usize(&mut reader)?, read_i64(&mut reader)? as isize)))
+                .collect::<Result<Vec<_>, SnapshotError>>()?;
+
+            Memory::sparse_from_cells(len, &cells)
+        } else {
+            let words = (0..len)
+                .map(|_| Ok(read_i64(&mut reader)? as isize))
+                .collect::<Result<Vec<_>, SnapshotError>>()?;
+
+            Memory::new(&words)
+        };
+
+        let memory = match limit {
+            Some(limit) => memory.with_limit(limit),
+            None => memory,
+        };
+
+        Ok(Self { memory, ip, relative_base, halted: flags & HALTED != 0, pending })
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+
+        // Writing to a `String` can't fail.
+        let _ = writeln!(text, "{} {}", TEXT_HEADER, VERSION);
+        let _ = writeln!(text, "ip {}", self.ip);
+        let _ = writeln!(text, "relative_base {}", self.relative_base);
+        let _ = writeln!(text, "halted {}", self.halted);
+
+        if let Some(limit) = self.memory.limit() {
+            let _ = writeln!(text, "limit {}", limit);
+        }
+
+        if !self.pending.is_empty() {
+            let _ = writeln!(text, "pending {}", join(self.pending.iter()));
+        }
+
+        if self.memory.is_sparse() {
+            let _ = write!(text, "sparse {}", self.memory.len());
+            for (address, n) in self.memory.nonzero_cells() {
+                let _ = write!(text, " {}={}", address, n);
+            }
+            text.push('\n');
+        } else {
+            let _ = writeln!(text, "memory {}", join(self.memory.to_vec().iter()));
+        }
+
+        text
+    }
+
+    pub fn from_text(text: &str) -> Result<Self, SnapshotError> {
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i + 1, line.split('#').next().unwrap_or("").trim()))
+            .filter(|(_, line)| !line.is_empty());
+
+        match lines.next() {
+            Some((line_no, header)) if header.starts_with(TEXT_HEADER) => {
+                let version = header[TEXT_HEADER.len()..].trim();
+                let version = version
+                    .parse::<u64>()
+                    .map_err(|_| malformed(line_no, format!("bad version `{}`", version)))?;
+
+                if version != u64::from(VERSION) {
+                    return Err(SnapshotError::UnsupportedVersion(version));
+                }
+            }
+            _ => return Err(SnapshotError::Malformed(String::from("missing snapshot header"))),
+        }
+
+        let (mut ip, mut relative_base, mut halted, mut limit) = (None, None, None, None);
+        let mut pending = None;
+        let mut memory = None;
+
+        for (line_no, line) in lines {
+            let mut parts = line.splitn(2, char::is_whitespace);
+            let key = parts.next().unwrap_or("");
+            let value = parts.next().unwrap_or("").trim();
+
+            let duplicate = match key {
+                "ip" => ip.replace(parse(line_no, value)?).is_some(),
+                "relative_base" => relative_base.replace(parse(line_no, value)?).is_some(),
+                "halted" => halted.replace(parse(line_no, value)?).is_some(),
+                "limit" => limit.replace(parse(line_no, value)?).is_some(),
+                "pending" => pending.replace(parse_list(line_no, value)?).is_some(),
+                "memory" => memory.replace(Memory::new(&parse_list(line_no, value)?)).is_some(),
+                "sparse" => memory.replace(parse_sparse(line_no, value)?).is_some(),
+                _ => return Err(malformed(line_no, format!("unknown key `{}`", key))),
+            };
+
+            if duplicate {
+                return Err(malformed(line_no, format!("`{}` given more than once", key)));
+            }
+        }
+
+        let missing = |key| SnapshotError::Malformed(format!("missing `{}`", key));
+        let memory = memory.ok_or_else(|| missing("memory"))?;
+
+        Ok(Self {
+            memory: match limit {
+                Some(limit) => memory.with_limit(limit),
+                None => memory,
+            },
+            ip: ip.ok_or_else(|| missing("ip"))?,
+            relative_base: relative_base.ok_or_else(|| missing("relative_base"))?,
+            halted: halted.ok_or_else(|| missing("halted"))?,
+            pending: pending.unwrap_or_default(),
+        })
+    }
+}
+
+fn join<'a>(values: impl Iterator<Item = &'a isize>) -> String {
+    values.map(isize::to_string).collect::<Vec<_>>().join(",")
+}
+
+fn malformed(line_no: usize, reason: String) -> SnapshotError {
+    SnapshotError::Malformed(format!("line {}: {}", line_no, reason))
+}
+
+fn parse<T: std::str::FromStr>(line_no: usize, value: &str) -> Result<T, SnapshotError> {
+    value.parse().map_err(|_| malformed(line_no, format!("invalid value `{}`", value)))
+}
+
+fn parse_list(line_no: usize, value: &str) -> Result<Vec<isize>, SnapshotError> {
+    if value.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    value.split(',').map(|n| parse(line_no, n.trim())).collect()
+}
+
+fn parse_sparse(line_no: usize, value: &str) -> Result<Memory, SnapshotError> {
+    let mut parts = value.split_whitespace();
+    let len = parse(line_no, parts.next().unwrap_or(""))?;
+    let cells = parts
+        .map(|cell| {
+            let mut cell = cell.splitn(2, '=');
+            let address = parse(line_no, cell.next().unwrap_or(""))?;
+            let n = parse(line_no, cell.next().unwrap_or(""))?;
+
+            Ok((address, n))
+        })
+        .collect::<Result<Vec<_>, SnapshotError>>()?;
+
+    Ok(Memory::sparse_from_cells(len, &cells))
+}
+
+fn write_u64<W: Write>(writer: &mut W, n: u64) -> io::Result<()> {
+    writer.write_all(&n.to_le_bytes())
+}
+
+fn write_i64<W: Write>(writer: &mut W, n: i64) -> io::Result<()> {
+    writer.write_all(&n.to_le_bytes())
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_i64<R: Read>(reader: &mut R) -> io::Result<i64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(i64::from_le_bytes(bytes))
+}
+
+fn read_usize<R: Read>(reader: &mut R) -> Result<usize, SnapshotError> {
+    let n = read_u64(reader)?;
+    std::convert::TryFrom::try_from(n)
+        .map_err(|_| SnapshotError::Malformed(format!("{} doesn't fit in usize", n)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::{empty, Event, IntcodeMachine};
+
+    fn paused_machine(memory: Memory) -> MachineState {
+        let mut machine = IntcodeMachine::with_memory(memory, empty(), ());
+        machine.provide_input(4);
+        assert_eq!(machine.run_until_event(), Ok(Event::NeedsInput));
+        machine.provide_input(5);
+        machine.provide_input(6);
+        machine.snapshot()
+    }
+
+    fn assert_same(a: &MachineState, b: &MachineState) {
+        assert_eq!((a.ip, a.relative_base, a.halted), (b.ip, b.relative_base, b.halted));
+        assert_eq!(a.pending, b.pending);
+        assert_eq!(a.memory.to_vec(), b.memory.to_vec());
+        assert_eq!(
+            (a.memory.is_sparse(), a.memory.limit()),
+            (b.memory.is_sparse(), b.memory.limit())
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let program = [109, 7, 203, 3, 3, 20, 99];
+        let states = vec![
+            paused_machine(Memory::new(&program)),
+            paused_machine(Memory::new(&program).with_limit(64)),
+            paused_machine(Memory::sparse(&program)),
+        ];
+
+        for state in &states {
+            let mut binary = Vec::new();
+            state.write_binary(&mut binary).unwrap();
+            assert_same(state, &MachineState::read_binary(&binary[..]).unwrap());
+            assert_same(state, &MachineState::from_text(&state.to_text()).unwrap());
+        }
+
+        let mut resumed = IntcodeMachine::from_state(
+            MachineState::from_text(&states[0].to_text()).unwrap(),
+            empty(),
+            (),
+        );
+        resumed.run().unwrap();
+        assert_eq!(resumed.data()[10], 4);
+        assert_eq!(resumed.data()[20], 5);
+    }
+
+    #[test]
+    fn text_errors() {
+        let text = "intcode-snapshot 2\nip 0\n";
+        assert!(matches!(MachineState::from_text(text), Err(SnapshotError::UnsupportedVersion(2))));
+
+        let text = "# saved by hand\n\nintcode-snapshot two\nip 0\n";
+        let err = MachineState::from_text(text).unwrap_err().to_string();
+        assert_eq!(err, "malformed snapshot: line 3: bad version `two`");
+
+        let text = "intcode-snapshot 1\nip 0\nip 1\n";
+        let err = MachineState::from_text(text).unwrap_err().to_string();
+        assert_eq!(err, "malformed snapshot: line 3: `ip` given more than once");
+    }
+}
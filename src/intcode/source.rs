@@ -0,0 +1,233 @@
+//! Where input instructions get their values from.
+//!
+//! Every `Iterator` of words is a `Source` that never blocks, so plain
+//! iterators keep working as machine input. The adapters here cover sources
+//! that can run dry without being finished, e.g. a channel with nothing in
+//! it yet.
+
+use super::Sink;
+use crossbeam_channel::{Receiver, TryRecvError};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+/// The counterpart to `Sink` for a machine's input.
+pub trait Source<N = isize> {
+    fn recv(&mut self) -> Received<N>;
+}
+
+/// What a `Source` had to give when asked for a value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Received<N = isize> {
+    Value(N),
+    /// Nothing is available yet, but there may be later.
+    WouldBlock,
+    /// Nothing is available and there never will be.
+    Closed,
+}
+
+impl<N> Received<N> {
+    pub fn value(self) -> Option<N> {
+        match self {
+            Received::Value(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+impl<N, I: Iterator<Item = N>> Source<N> for I {
+    fn recv(&mut self) -> Received<N> {
+        self.next().map_or(Received::Closed, Received::Value)
+    }
+}
+
+/// What an input instruction does when its machine's `Source` reports
+/// `Received::WouldBlock`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OnWouldBlock<N = isize> {
+    /// Stops with `ErrorKind::InputExhausted`, leaving `ip` on the input
+    /// instruction so it's retried when the machine is resumed.
+    /// `IntcodeMachine::run_until_event` reports this as `Event::NeedsInput`.
+    #[default]
+    Suspend,
+    /// Keeps asking, yielding the thread in between.
+    Wait,
+    /// Reads the given value instead, e.g. `-1` for a network card with no
+    /// packets queued.
+    Supply(N),
+}
+
+/// What an input instruction does when its machine's `Source` reports
+/// `Received::Closed`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OnClosed<N = isize> {
+    /// Same as `OnWouldBlock::Suspend`. Inputs queued with
+    /// `IntcodeMachine::provide_input` are still read.
+    #[default]
+    Suspend,
+    /// Reads the given value instead.
+    Supply(N),
+}
+
+/// How a machine treats a `Source` that has no value to give.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InputPolicy<N = isize> {
+    pub would_block: OnWouldBlock<N>,
+    pub closed: OnClosed<N>,
+}
+
+impl<N> Default for InputPolicy<N> {
+    fn default() -> Self {
+        Self { would_block: OnWouldBlock::default(), closed: OnClosed::default() }
+    }
+}
+
+/// Reads from a channel without blocking: an empty channel is
+/// `Received::WouldBlock` and a disconnected one is `Received::Closed`. To
+/// block until a value arrives instead, use `receiver.into_iter()`.
+#[derive(Clone, Debug)]
+pub struct Channel(pub Receiver<isize>);
+
+impl Source for Channel {
+    fn recv(&mut self) -> Received {
+        match self.0.try_recv() {
+            Ok(value) => Received::Value(value),
+            Err(TryRecvError::Empty) => Received::WouldBlock,
+            Err(TryRecvError::Disconnected) => Received::Closed,
+        }
+    }
+}
+
+/// A queue shared between a machine and whoever feeds it, for single
+/// threaded setups. An empty queue is `Received::WouldBlock`; it's never
+/// closed.
+#[derive(Clone, Debug, Default)]
+pub struct Queue(Rc<RefCell<VecDeque<isize>>>);
+
+impl Queue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&self, value: isize) {
+        self.0.borrow_mut().push_back(value);
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.borrow().is_empty()
+    }
+
+    pub fn clear(&self) {
+        self.0.borrow_mut().clear();
+    }
+}
+
+impl From<VecDeque<isize>> for Queue {
+    fn from(values: VecDeque<isize>) -> Self {
+        Self(Rc::new(RefCell::new(values)))
+    }
+}
+
+impl Sink<isize> for Queue {
+    fn send(&mut self, item: isize) {
+        self.push(item);
+    }
+}
+
+impl Source for Queue {
+    fn recv(&mut self) -> Received {
+        self.0.borrow_mut().pop_front().map_or(Received::WouldBlock, Received::Value)
+    }
+}
+
+/// A source that calls a closure for each value.
+#[derive(Clone)]
+pub struct FromFn<F>(F);
+
+pub fn from_fn<N, F: FnMut() -> Received<N>>(f: F) -> FromFn<F> {
+    FromFn(f)
+}
+
+impl<N, F: FnMut() -> Received<N>> Source<N> for FromFn<F> {
+    fn recv(&mut self) -> Received<N> {
+        (self.0)()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::{ErrorKind, Event, IntcodeMachine};
+
+    /// Echoes two inputs.
+    const ECHO: [isize; 9] = [3, 0, 4, 0, 3, 0, 4, 0, 99];
+
+    #[test]
+    fn channel_would_block() {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        let mut machine = IntcodeMachine::new(&ECHO, Channel(receiver), Vec::new());
+
+        sender.send(1).unwrap();
+        assert_eq!(machine.run_until_event(), Ok(Event::Output(1)));
+        assert_eq!(machine.run_until_event(), Ok(Event::NeedsInput));
+
+        sender.send(2).unwrap();
+        drop(sender);
+        assert_eq!(machine.run_until_event(), Ok(Event::Output(2)));
+        assert_eq!(machine.run_until_event(), Ok(Event::Halted));
+    }
+
+    #[test]
+    fn supply_when_empty() {
+        let queue = Queue::new();
+        let policy =
+            InputPolicy { would_block: OnWouldBlock::Supply(-1), ..InputPolicy::default() };
+        let mut machine =
+            IntcodeMachine::new(&ECHO, queue.clone(), Vec::new()).with_input_policy(policy);
+
+        queue.push(7);
+        machine.run().unwrap();
+        assert_eq!(machine.output_mut(), &[7, -1]);
+        assert!(queue.is_empty());
+
+        let policy = InputPolicy { closed: OnClosed::Supply(0), ..InputPolicy::default() };
+        let mut machine =
+            IntcodeMachine::new(&ECHO, vec![5].into_iter(), Vec::new()).with_input_policy(policy);
+        machine.run().unwrap();
+        assert_eq!(machine.output_mut(), &[5, 0]);
+    }
+
+    #[test]
+    fn closed_suspends_by_default() {
+        let mut values = vec![Received::Closed, Received::WouldBlock, Received::Value(3)];
+        let mut machine = IntcodeMachine::new(&ECHO, from_fn(|| values.remove(0)), Vec::new());
+
+        assert_eq!(machine.run().unwrap_err().kind, ErrorKind::InputExhausted);
+        assert_eq!(machine.run().unwrap_err().kind, ErrorKind::InputExhausted);
+
+        // Queued inputs are read before asking the source again.
+        machine.provide_input(4);
+        machine.run().unwrap();
+        assert_eq!(machine.output_mut(), &[4, 3]);
+    }
+
+    #[test]
+    fn wait_until_available() {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        let policy = InputPolicy { would_block: OnWouldBlock::Wait, ..InputPolicy::default() };
+
+        let handle = std::thread::spawn(move || {
+            let mut machine =
+                IntcodeMachine::new(&ECHO, Channel(receiver), Vec::new()).with_input_policy(policy);
+            machine.run().map(|_| machine.output_mut().clone())
+        });
+
+        sender.send(1).unwrap();
+        sender.send(2).unwrap();
+        assert_eq!(handle.join().unwrap(), Ok(vec![1, 2]));
+    }
+}
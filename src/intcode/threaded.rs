@@ -0,0 +1,459 @@
+//! Threaded-code execution engine.
+//!
+//! The first time an address is executed its instruction is translated into
+//! an `Op`: a plain function pointer chosen for the exact combination of
+//! operand modes, bound to the raw operand values. Running an `Op` skips
+//! decoding and every per-operand mode check the interpreter makes, while
+//! still going through the machine's `read`/`write` so tracing, memory
+//! limits and faults behave identically.
+//!
+//! Self-modifying code is handled conservatively: once a write lands inside
+//! a translated instruction that address is marked as modified and is run
+//! by the interpreter from then on.
+
+use super::cache::{MAX_ADDRESS, MAX_SIZE};
+use super::{
+    address, Destination, ErrorKind, Instruction, Instructions, IntcodeMachine, Operand, Sink,
+    Source, Tracer, Word,
+};
+
+type Machine<R, W, T, N> = IntcodeMachine<R, W, T, N>;
+pub(crate) type Handler<R, W, T, N> =
+    fn(&mut Machine<R, W, T, N>, &[N; 3]) -> Result<(), ErrorKind>;
+
+pub(crate) struct Op<R: Source<N>, W: Sink<N>, T: Tracer<N>, N: Word> {
+    /// Executes the instruction, with `ip` already advanced past it.
+    pub(crate) handler: Handler<R, W, T, N>,
+    pub(crate) args: [N; 3],
+    pub(crate) size: usize,
+    /// The instruction the op was translated from, for tracers.
+    pub(crate) instruction: Instructions<N>,
+}
+
+impl<R: Source<N>, W: Sink<N>, T: Tracer<N>, N: Word> Clone for Op<R, W, T, N> {
+    fn clone(&self) -> Self {
+        Self {
+            handler: self.handler,
+            args: self.args.clone(),
+            size: self.size,
+            instruction: self.instruction.clone(),
+        }
+    }
+}
+
+enum Slot<R: Source<N>, W: Sink<N>, T: Tracer<N>, N: Word> {
+    Empty,
+    Compiled(Op<R, W, T, N>),
+    Modified,
+}
+
+impl<R: Source<N>, W: Sink<N>, T: Tracer<N>, N: Word> Clone for Slot<R, W, T, N> {
+    fn clone(&self) -> Self {
+        match self {
+            Slot::Empty => Slot::Empty,
+            Slot::Compiled(op) => Slot::Compiled(op.clone()),
+            Slot::Modified => Slot::Modified,
+        }
+    }
+}
+
+pub(crate) struct CodeTable<R: Source<N>, W: Sink<N>, T: Tracer<N>, N: Word> {
+    slots: Vec<Slot<R, W, T, N>>,
+}
+
+impl<R: Source<N>, W: Sink<N>, T: Tracer<N>, N: Word> Clone for CodeTable<R, W, T, N> {
+    fn clone(&self) -> Self {
+        Self { slots: self.slots.clone() }
+    }
+}
+
+impl<R: Source<N>, W: Sink<N>, T: Tracer<N>, N: Word> CodeTable<R, W, T, N> {
+    pub(crate) fn new() -> Self {
+        Self { slots: Vec::new() }
+    }
+
+    pub(crate) fn get(&self, address: usize) -> Option<&Op<R, W, T, N>> {
+        match self.slots.get(address) {
+            Some(Slot::Compiled(op)) => Some(op),
+            _ => None,
+        }
+    }
+
+    /// Translates `instruction`, found at `address`, unless the code there
+    /// has been modified since it was first translated.
+    /// Custom instructions are always left to the interpreter.
+    pub(crate) fn compile(&mut self, address: usize, instruction: Instructions<N>) {
+        if address >= MAX_ADDRESS || matches!(instruction, Instructions::Extension(_)) {
+            return;
+        }
+
+        if address >= self.slots.len() {
+            self.slots.resize(address + 1, Slot::Empty);
+        }
+
+        if let Slot::Empty = self.slots[address] {
+            self.slots[address] = Slot::Compiled(compile(instruction));
+        }
+    }
+
+    /// Marks every translated instruction that covers `address` as modified.
+    pub(crate) fn invalidate(&mut self, address: usize) {
+        let start = address.saturating_sub(MAX_SIZE - 1);
+        let end = (address + 1).min(self.slots.len());
+
+        for at in start..end {
+            if let Slot::Compiled(op) = &self.slots[at] {
+                if at + op.size > address {
+                    self.slots[at] = Slot::Modified;
+                }
+            }
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.slots.clear();
+    }
+}
+
+/// How an operand is fetched, decided once at translation time.
+trait Load {
+    fn load<R: Source<N>, W: Sink<N>, T: Tracer<N>, N: Word>(
+        machine: &mut Machine<R, W, T, N>,
+        arg: &N,
+    ) -> Result<N, ErrorKind>;
+}
+
+/// How a destination address is computed.
+trait Store {
+    fn address<R: Source<N>, W: Sink<N>, T: Tracer<N>, N: Word>(
+        machine: &Machine<R, W, T, N>,
+        arg: &N,
+    ) -> Result<usize, ErrorKind>;
+}
+
+struct Imm;
+struct Pos;
+struct Rel;
+
+/// A position operand's address. They were checked to fit in a `usize`
+/// when decoded.
+#[inline(always)]
+fn position<N: Word>(arg: &N) -> usize {
+    arg.to_isize().unwrap_or_default() as usize
+}
+
+impl Load for Imm {
+    #[inline(always)]
+    fn load<R: Source<N>, W: Sink<N>, T: Tracer<N>, N: Word>(
+        _: &mut Machine<R, W, T, N>,
+        arg: &N,
+    ) -> Result<N, ErrorKind> {
+        Ok(arg.clone())
+    }
+}
+
+impl Load for Pos {
+    #[inline(always)]
+    fn load<R: Source<N>, W: Sink<N>, T: Tracer<N>, N: Word>(
+        machine: &mut Machine<R, W, T, N>,
+        arg: &N,
+    ) -> Result<N, ErrorKind> {
+        machine.read(position(arg))
+    }
+}
+
+impl Load for Rel {
+    #[inline(always)]
+    fn load<R: Source<N>, W: Sink<N>, T: Tracer<N>, N: Word>(
+        machine: &mut Machine<R, W, T, N>,
+        arg: &N,
+    ) -> Result<N, ErrorKind> {
+        machine.read(address(machine.add(&machine.relative_base, arg)?)?)
+    }
+}
+
+impl Store for Pos {
+    #[inline(always)]
+    fn address<R: Source<N>, W: Sink<N>, T: Tracer<N>, N: Word>(
+        _: &Machine<R, W, T, N>,
+        arg: &N,
+    ) -> Result<usize, ErrorKind> {
+        Ok(position(arg))
+    }
+}
+
+impl Store for Rel {
+    #[inline(always)]
+    fn address<R: Source<N>, W: Sink<N>, T: Tracer<N>, N: Word>(
+        machine: &Machine<R, W, T, N>,
+        arg: &N,
+    ) -> Result<usize, ErrorKind> {
+        address(machine.add(&machine.relative_base, arg)?)
+    }
+}
+
+/// The instructions taking two operands and a destination.
+#[derive(Clone, Copy)]
+enum Binary {
+    Add,
+    Mul,
+    LessThan,
+    EqualTo,
+}
+
+/// The instructions taking two operands.
+#[derive(Clone, Copy)]
+enum Jump {
+    IfTrue,
+    IfFalse,
+}
+
+fn compile<R: Source<N>, W: Sink<N>, T: Tracer<N>, N: Word>(
+    instruction: Instructions<N>,
+) -> Op<R, W, T, N> {
+    let (handler, args): (Handler<R, W, T, N>, _) = match &instruction {
+        Instructions::Add(i) => binary(Binary::Add, &i.op1, &i.op2, &i.dst),
+        Instructions::Mul(i) => binary(Binary::Mul, &i.op1, &i.op2, &i.dst),
+        Instructions::LessThan(i) => binary(Binary::LessThan, &i.op1, &i.op2, &i.dst),
+        Instructions::EqualTo(i) => binary(Binary::EqualTo, &i.op1, &i.op2, &i.dst),
+        Instructions::JumpIfTrue(i) => jump(Jump::IfTrue, &i.test, &i.jump_to),
+        Instructions::JumpIfFalse(i) => jump(Jump::IfFalse, &i.test, &i.jump_to),
+        Instructions::Input(i) => match &i.operand {
+            Destination::Position(p) => (input::<Pos, R, W, T, N>, args(position_arg(*p))),
+            Destination::Relative(r) => (input::<Rel, R, W, T, N>, args(r.clone())),
+        },
+        Instructions::Output(i) => {
+            let arg = args(arg(&i.operand));
+            match i.operand {
+                Operand::Immediate(_) => (output::<Imm, R, W, T, N>, arg),
+                Operand::Position(_) => (output::<Pos, R, W, T, N>, arg),
+                Operand::Relative(_) => (output::<Rel, R, W, T, N>, arg),
+            }
+        }
+        Instructions::ModRelBase(i) => {
+            let arg = args(arg(&i.operand));
+            match i.operand {
+                Operand::Immediate(_) => (relative_base::<Imm, R, W, T, N>, arg),
+                Operand::Position(_) => (relative_base::<Pos, R, W, T, N>, arg),
+                Operand::Relative(_) => (relative_base::<Rel, R, W, T, N>, arg),
+            }
+        }
+        Instructions::Halt(_) => (halt::<R, W, T, N>, args(N::ZERO.clone())),
+        Instructions::Extension(_) => unreachable!("custom instructions aren't translated"),
+    };
+
+    Op { handler, args, size: instruction.size(), instruction }
+}
+
+fn arg<N: Word>(operand: &Operand<N>) -> N {
+    match operand {
+        Operand::Immediate(n) | Operand::Relative(n) => n.clone(),
+        Operand::Position(p) => position_arg(*p),
+    }
+}
+
+fn position_arg<N: Word>(address: usize) -> N {
+    N::from_isize(address as isize)
+}
+
+/// The arguments of an op taking one.
+fn args<N: Word>(first: N) -> [N; 3] {
+    [first, N::ZERO.clone(), N::ZERO.clone()]
+}
+
+fn binary<R: Source<N>, W: Sink<N>, T: Tracer<N>, N: Word>(
+    kind: Binary,
+    op1: &Operand<N>,
+    op2: &Operand<N>,
+    dst: &Destination<N>,
+) -> (Handler<R, W, T, N>, [N; 3]) {
+    let args = match dst {
+        Destination::Position(p) => [arg(op1), arg(op2), position_arg(*p)],
+        Destination::Relative(r) => [arg(op1), arg(op2), r.clone()],
+    };
+
+    let run = match op1 {
+        Operand::Immediate(_) => binary_b::<Imm, R, W, T, N>(kind, op2, dst),
+        Operand::Position(_) => binary_b::<Pos, R, W, T, N>(kind, op2, dst),
+        Operand::Relative(_) => binary_b::<Rel, R, W, T, N>(kind, op2, dst),
+    };
+
+    (run, args)
+}
+
+fn binary_b<A: Load, R: Source<N>, W: Sink<N>, T: Tracer<N>, N: Word>(
+    kind: Binary,
+    op2: &Operand<N>,
+    dst: &Destination<N>,
+) -> Handler<R, W, T, N> {
+    match op2 {
+        Operand::Immediate(_) => binary_c::<A, Imm, R, W, T, N>(kind, dst),
+        Operand::Position(_) => binary_c::<A, Pos, R, W, T, N>(kind, dst),
+        Operand::Relative(_) => binary_c::<A, Rel, R, W, T, N>(kind, dst),
+    }
+}
+
+fn binary_c<A: Load, B: Load, R: Source<N>, W: Sink<N>, T: Tracer<N>, N: Word>(
+    kind: Binary,
+    dst: &Destination<N>,
+) -> Handler<R, W, T, N> {
+    match dst {
+        Destination::Position(_) => binary_d::<A, B, Pos, R, W, T, N>(kind),
+        Destination::Relative(_) => binary_d::<A, B, Rel, R, W, T, N>(kind),
+    }
+}
+
+fn binary_d<A: Load, B: Load, D: Store, R: Source<N>, W: Sink<N>, T: Tracer<N>, N: Word>(
+    kind: Binary,
+) -> Handler<R, W, T, N> {
+    match kind {
+        Binary::Add => add::<A, B, D, R, W, T, N>,
+        Binary::Mul => mul::<A, B, D, R, W, T, N>,
+        Binary::LessThan => less_than::<A, B, D, R, W, T, N>,
+        Binary::EqualTo => equal_to::<A, B, D, R, W, T, N>,
+    }
+}
+
+fn jump<R: Source<N>, W: Sink<N>, T: Tracer<N>, N: Word>(
+    kind: Jump,
+    test: &Operand<N>,
+    jump_to: &Operand<N>,
+) -> (Handler<R, W, T, N>, [N; 3]) {
+    let run = match test {
+        Operand::Immediate(_) => jump_b::<Imm, R, W, T, N>(kind, jump_to),
+        Operand::Position(_) => jump_b::<Pos, R, W, T, N>(kind, jump_to),
+        Operand::Relative(_) => jump_b::<Rel, R, W, T, N>(kind, jump_to),
+    };
+
+    (run, [arg(test), arg(jump_to), N::ZERO.clone()])
+}
+
+fn jump_b<A: Load, R: Source<N>, W: Sink<N>, T: Tracer<N>, N: Word>(
+    kind: Jump,
+    jump_to: &Operand<N>,
+) -> Handler<R, W, T, N> {
+    match (kind, jump_to) {
+        (Jump::IfTrue, Operand::Immediate(_)) => jump_if_true::<A, Imm, R, W, T, N>,
+        (Jump::IfTrue, Operand::Position(_)) => jump_if_true::<A, Pos, R, W, T, N>,
+        (Jump::IfTrue, Operand::Relative(_)) => jump_if_true::<A, Rel, R, W, T, N>,
+        (Jump::IfFalse, Operand::Immediate(_)) => jump_if_false::<A, Imm, R, W, T, N>,
+        (Jump::IfFalse, Operand::Position(_)) => jump_if_false::<A, Pos, R, W, T, N>,
+        (Jump::IfFalse, Operand::Relative(_)) => jump_if_false::<A, Rel, R, W, T, N>,
+    }
+}
+
+fn add<A: Load, B: Load, D: Store, R: Source<N>, W: Sink<N>, T: Tracer<N>, N: Word>(
+    machine: &mut Machine<R, W, T, N>,
+    args: &[N; 3],
+) -> Result<(), ErrorKind> {
+    let op1 = A::load(machine, &args[0])?;
+    let op2 = B::load(machine, &args[1])?;
+    let dst = D::address(machine, &args[2])?;
+
+    let value = machine.add(&op1, &op2)?;
+
+    machine.write(dst, value)
+}
+
+fn mul<A: Load, B: Load, D: Store, R: Source<N>, W: Sink<N>, T: Tracer<N>, N: Word>(
+    machine: &mut Machine<R, W, T, N>,
+    args: &[N; 3],
+) -> Result<(), ErrorKind> {
+    let op1 = A::load(machine, &args[0])?;
+    let op2 = B::load(machine, &args[1])?;
+    let dst = D::address(machine, &args[2])?;
+
+    let value = machine.mul(&op1, &op2)?;
+
+    machine.write(dst, value)
+}
+
+fn less_than<A: Load, B: Load, D: Store, R: Source<N>, W: Sink<N>, T: Tracer<N>, N: Word>(
+    machine: &mut Machine<R, W, T, N>,
+    args: &[N; 3],
+) -> Result<(), ErrorKind> {
+    let dst = D::address(machine, &args[2])?;
+    let op1 = A::load(machine, &args[0])?;
+    let op2 = B::load(machine, &args[1])?;
+
+    machine.write(dst, N::from_isize((op1 < op2) as isize))
+}
+
+fn equal_to<A: Load, B: Load, D: Store, R: Source<N>, W: Sink<N>, T: Tracer<N>, N: Word>(
+    machine: &mut Machine<R, W, T, N>,
+    args: &[N; 3],
+) -> Result<(), ErrorKind> {
+    let dst = D::address(machine, &args[2])?;
+    let op1 = A::load(machine, &args[0])?;
+    let op2 = B::load(machine, &args[1])?;
+
+    machine.write(dst, N::from_isize((op1 == op2) as isize))
+}
+
+fn jump_if_true<A: Load, B: Load, R: Source<N>, W: Sink<N>, T: Tracer<N>, N: Word>(
+    machine: &mut Machine<R, W, T, N>,
+    args: &[N; 3],
+) -> Result<(), ErrorKind> {
+    if !A::load(machine, &args[0])?.is_zero() {
+        let jump_to = address(B::load(machine, &args[1])?)?;
+        machine.tracer.jump(machine.ip - 3, jump_to);
+        machine.ip = jump_to;
+    }
+
+    Ok(())
+}
+
+fn jump_if_false<A: Load, B: Load, R: Source<N>, W: Sink<N>, T: Tracer<N>, N: Word>(
+    machine: &mut Machine<R, W, T, N>,
+    args: &[N; 3],
+) -> Result<(), ErrorKind> {
+    if A::load(machine, &args[0])?.is_zero() {
+        let jump_to = address(B::load(machine, &args[1])?)?;
+        machine.tracer.jump(machine.ip - 3, jump_to);
+        machine.ip = jump_to;
+    }
+
+    Ok(())
+}
+
+fn input<D: Store, R: Source<N>, W: Sink<N>, T: Tracer<N>, N: Word>(
+    machine: &mut Machine<R, W, T, N>,
+    args: &[N; 3],
+) -> Result<(), ErrorKind> {
+    let dst = D::address(machine, &args[0])?;
+    let inp = machine.input()?;
+
+    machine.write(dst, inp)
+}
+
+fn output<A: Load, R: Source<N>, W: Sink<N>, T: Tracer<N>, N: Word>(
+    machine: &mut Machine<R, W, T, N>,
+    args: &[N; 3],
+) -> Result<(), ErrorKind> {
+    let val = A::load(machine, &args[0])?;
+
+    machine.output(val);
+
+    Ok(())
+}
+
+fn relative_base<A: Load, R: Source<N>, W: Sink<N>, T: Tracer<N>, N: Word>(
+    machine: &mut Machine<R, W, T, N>,
+    args: &[N; 3],
+) -> Result<(), ErrorKind> {
+    let value = A::load(machine, &args[0])?;
+
+    machine.relative_base = machine.add(&machine.relative_base, &value)?;
+    machine.tracer.relative_base(machine.relative_base.clone());
+
+    Ok(())
+}
+
+fn halt<R: Source<N>, W: Sink<N>, T: Tracer<N>, N: Word>(
+    machine: &mut Machine<R, W, T, N>,
+    _: &[N; 3],
+) -> Result<(), ErrorKind> {
+    machine.halt();
+
+    Ok(())
+}
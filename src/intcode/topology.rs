@@ -0,0 +1,342 @@
+//! Networks of machines running the same program, each feeding its output
+//! to the inputs of others, like day 7's amplifiers.
+//!
+//! Every node reads from a `Queue` and sends its output to the queues of all
+//! the nodes it's connected to, chained together with the tuple `Sink` impl.
+//! Nodes are run in turn on the calling thread until they've all halted, so
+//! the order values arrive in is the same on every run. `Topology::search`
+//! runs many of these in parallel to find the best phase settings.
+
+use super::source::Queue;
+use super::{empty, Event, IntcodeError, IntcodeMachine, MachineState, Sink};
+use itertools::Itertools;
+use rayon::prelude::*;
+use std::fmt;
+
+/// Sends every value to each of a node's successors: `(q1, (q2, ..., ()))`,
+/// boxed since the number of successors is only known at run time.
+type Fanout = Box<dyn Sink<isize>>;
+
+#[derive(Debug)]
+pub enum TopologyError {
+    Machine {
+        node: usize,
+        error: IntcodeError,
+    },
+    /// Every node that hasn't halted is waiting for input no other node will
+    /// send.
+    Deadlock {
+        waiting: Vec<usize>,
+    },
+}
+
+impl fmt::Display for TopologyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TopologyError::Machine { node, error } => write!(f, "node {}: {}", node, error),
+            TopologyError::Deadlock { waiting } => {
+                write!(f, "deadlock with nodes {:?} waiting for input", waiting)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TopologyError {}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Topology {
+    nodes: usize,
+    edges: Vec<(usize, usize)>,
+}
+
+impl Topology {
+    /// `nodes` machines with nothing connected.
+    pub fn new(nodes: usize) -> Self {
+        Self { nodes, edges: Vec::new() }
+    }
+
+    /// Each node feeding the next.
+    pub fn chain(nodes: usize) -> Self {
+        (1..nodes).fold(Self::new(nodes), |topology, node| topology.connect(node - 1, node))
+    }
+
+    /// A chain with the last node feeding back into the first.
+    pub fn ring(nodes: usize) -> Self {
+        match nodes {
+            0 => Self::new(0),
+            _ => Self::chain(nodes).connect(nodes - 1, 0),
+        }
+    }
+
+    /// Sends the output of `from` to `to` as well as anywhere else it goes.
+    pub fn connect(mut self, from: usize, to: usize) -> Self {
+        assert!(from < self.nodes && to < self.nodes, "no node {} in topology", from.max(to));
+
+        self.edges.push((from, to));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes == 0
+    }
+
+    /// Runs a copy of `program` at each node until they've all halted and
+    /// returns everything each node output, by node.
+    ///
+    /// Each node first reads its entry in `phases`, if there is one, and node
+    /// 0 then reads `seed` before any values from other nodes.
+    pub fn run(
+        &self,
+        program: &[isize],
+        phases: &[isize],
+        seed: &[isize],
+    ) -> Result<Vec<Vec<isize>>, TopologyError> {
+        Nodes::new(self, program).run(phases, seed)
+    }
+
+    /// Runs the topology with every candidate in `domain` as the phases, in
+    /// parallel, and returns the one whose outputs give the highest `key`.
+    /// On a tie the candidate that comes first in `domain` wins.
+    ///
+    /// Candidates are generated as they're needed rather than all up front,
+    /// and a set of machines is only built once per batch of candidates
+    /// rayon hands to a thread. Returns `None` if `domain` has no
+    /// candidates, and the first error hit by any candidate.
+    pub fn search<K, F>(
+        &self,
+        program: &[isize],
+        seed: &[isize],
+        domain: &Domain,
+        key: F,
+    ) -> Result<Option<Best<K>>, TopologyError>
+    where
+        K: Ord + Send,
+        F: Fn(&[Vec<isize>]) -> K + Sync,
+    {
+        // Bridging loses the domain's order, so each candidate carries its
+        // index to break ties with.
+        let best = domain
+            .candidates(self.nodes)
+            .enumerate()
+            .par_bridge()
+            .map_init(
+                || Nodes::new(self, program),
+                |nodes, (index, phases)| {
+                    let outputs = nodes.run(&phases, seed)?;
+                    Ok((index, Best { key: key(&outputs), phases, outputs }))
+                },
+            )
+            .try_reduce_with(|a, b| {
+                let b_wins = b.1.key > a.1.key || (b.1.key == a.1.key && b.0 < a.0);
+                Ok(if b_wins { b } else { a })
+            })
+            .transpose()?;
+
+        Ok(best.map(|(_, best)| best))
+    }
+}
+
+/// The values to try as phases in `Topology::search`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Domain {
+    /// Every ordering of distinct values from the list, one per node.
+    Permutations(Vec<isize>),
+    /// Every choice of distinct values from the list, one per node, given to
+    /// the nodes in list order. For when the order doesn't matter.
+    Combinations(Vec<isize>),
+    /// Every way of giving each node any value from the list.
+    Product(Vec<isize>),
+}
+
+impl Domain {
+    fn candidates(&self, nodes: usize) -> Box<dyn Iterator<Item = Vec<isize>> + Send + '_> {
+        match self {
+            Domain::Permutations(values) => Box::new(values.iter().copied().permutations(nodes)),
+            Domain::Combinations(values) => Box::new(values.iter().copied().combinations(nodes)),
+            Domain::Product(_) if nodes == 0 => Box::new(std::iter::once(Vec::new())),
+            Domain::Product(values) => {
+                Box::new((0..nodes).map(|_| values.iter().copied()).multi_cartesian_product())
+            }
+        }
+    }
+}
+
+/// The result of `Topology::search`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Best<K> {
+    pub key: K,
+    pub phases: Vec<isize>,
+    /// Everything each node output, by node.
+    pub outputs: Vec<Vec<isize>>,
+}
+
+type Node = IntcodeMachine<Queue, (Fanout, Vec<isize>)>;
+
+/// A topology's machines and queues, wired up and ready to be run as many
+/// times as needed.
+struct Nodes {
+    machines: Vec<Node>,
+    queues: Vec<Queue>,
+    boot: MachineState,
+}
+
+impl Nodes {
+    fn new(topology: &Topology, program: &[isize]) -> Self {
+        let queues: Vec<_> = (0..topology.nodes).map(|_| Queue::new()).collect();
+
+        let machines: Vec<_> = (0..topology.nodes)
+            .map(|node| {
+                let targets = topology.edges.iter().filter(|&&(from, _)| from == node);
+                let fanout = targets.rev().fold(Box::new(()) as Fanout, |rest, &(_, to)| {
+                    Box::new((queues[to].clone(), rest))
+                });
+
+                IntcodeMachine::new(program, queues[node].clone(), (fanout, Vec::new()))
+            })
+            .collect();
+
+        let boot = IntcodeMachine::new(program, empty(), ()).snapshot();
+
+        Self { machines, queues, boot }
+    }
+
+    fn run(&mut self, phases: &[isize], seed: &[isize]) -> Result<Vec<Vec<isize>>, TopologyError> {
+        let Self { machines, queues, boot } = self;
+
+        for (machine, queue) in machines.iter_mut().zip(queues.iter()) {
+            machine.restore(boot.clone());
+            machine.output_mut().1.clear();
+            queue.clear();
+        }
+
+        for (queue, &phase) in queues.iter().zip(phases) {
+            queue.push(phase);
+        }
+        for &value in seed {
+            queues[0].push(value);
+        }
+
+        loop {
+            let mut progress = false;
+
+            for (node, machine) in machines.iter_mut().enumerate() {
+                if machine.is_halted() {
+                    continue;
+                }
+
+                loop {
+                    match machine.run_until_event() {
+                        Ok(Event::Output(_)) => progress = true,
+                        Ok(Event::Halted) => {
+                            progress = true;
+                            break;
+                        }
+                        Ok(Event::NeedsInput) => break,
+                        Err(error) => return Err(TopologyError::Machine { node, error }),
+                    }
+                }
+            }
+
+            let waiting: Vec<_> =
+                (0..machines.len()).filter(|&n| !machines[n].is_halted()).collect();
+
+            if waiting.is_empty() {
+                break;
+            } else if !progress {
+                return Err(TopologyError::Deadlock { waiting });
+            }
+        }
+
+        Ok(machines.iter_mut().map(|machine| std::mem::take(&mut machine.output_mut().1)).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::asm;
+
+    /// Reads as many values as its phase says, then outputs their sum.
+    const SUM: &str = "
+        input pos(n)
+loop:   input pos(x)
+        add pos(sum), pos(x), pos(sum)
+        add pos(n), imm(-1), pos(n)
+        jit pos(n), imm(loop)
+        output pos(sum)
+        halt
+n:      .data 0
+x:      .data 0
+sum:    .data 0
+";
+
+    #[test]
+    fn fan_out_and_in() {
+        let program = asm::assemble(SUM).unwrap();
+
+        // 0 feeds both 1 and 2, which both feed 3.
+        let topology = Topology::new(4).connect(0, 1).connect(0, 2).connect(1, 3).connect(2, 3);
+        let outputs = topology.run(&program, &[1, 1, 1, 2], &[5]).unwrap();
+
+        assert_eq!(outputs, vec![vec![5], vec![5], vec![5], vec![10]]);
+    }
+
+    #[test]
+    fn deadlock() {
+        let program = asm::assemble(SUM).unwrap();
+
+        match Topology::chain(3).run(&program, &[1, 2, 1], &[5]) {
+            Err(TopologyError::Deadlock { waiting }) => assert_eq!(waiting, vec![1, 2]),
+            result => panic!("expected deadlock, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn examples() {
+        let program = [3, 15, 3, 16, 1002, 16, 10, 16, 1, 16, 15, 15, 4, 15, 99, 0, 0];
+        let outputs = Topology::chain(5).run(&program, &[4, 3, 2, 1, 0], &[0]).unwrap();
+        assert_eq!(outputs, vec![vec![4], vec![43], vec![432], vec![4321], vec![43210]]);
+
+        let program = [
+            3, 26, 1001, 26, -4, 26, 3, 27, 1002, 27, 2, 27, 1, 27, 26, 27, 4, 27, 1001, 28, -1,
+            28, 1005, 28, 6, 99, 0, 0, 5,
+        ];
+        let outputs = Topology::ring(5).run(&program, &[9, 8, 7, 6, 5], &[0]).unwrap();
+        assert_eq!(outputs[4].last(), Some(&139_629_729));
+    }
+
+    #[test]
+    fn search() {
+        // Outputs its input times 10 plus its phase.
+        let program = [3, 15, 3, 16, 1002, 16, 10, 16, 1, 16, 15, 15, 4, 15, 99, 0, 0];
+        let last = |outputs: &[Vec<isize>]| outputs.last().and_then(|o| o.last()).copied();
+
+        let domain = Domain::Permutations((0..=4).collect());
+        let best = Topology::chain(5).search(&program, &[0], &domain, last).unwrap().unwrap();
+        assert_eq!(best.key, Some(43210));
+        assert_eq!(best.phases, vec![4, 3, 2, 1, 0]);
+        assert_eq!(best.outputs[..2], [vec![4], vec![43]]);
+
+        let domain = Domain::Combinations((0..=4).collect());
+        assert_eq!(domain.candidates(3).count(), 10);
+        let best = Topology::chain(3).search(&program, &[0], &domain, last).unwrap().unwrap();
+        assert_eq!(best.phases, vec![2, 3, 4]);
+        assert_eq!(best.key, Some(234));
+
+        let domain = Domain::Product(vec![2, 1]);
+        assert_eq!(domain.candidates(3).count(), 8);
+        let best = Topology::chain(3).search(&program, &[0], &domain, last).unwrap().unwrap();
+        assert_eq!(best.phases, vec![2, 2, 2]);
+
+        // Every candidate ties, so the first wins.
+        let domain = Domain::Product(vec![1, 2]);
+        let best = Topology::chain(3).search(&program, &[0], &domain, |_| 0).unwrap().unwrap();
+        assert_eq!(best.phases, vec![1, 1, 1]);
+        let domain = Domain::Permutations(vec![]);
+        assert_eq!(Topology::chain(1).search(&program, &[0], &domain, |_| 0).unwrap(), None);
+    }
+}
@@ -0,0 +1,351 @@
+//! Hooks for observing a machine as it runs.
+//!
+//! A `Tracer` is a type parameter of `IntcodeMachine`, defaulting to
+//! `NoTracer` whose hooks are empty, so untraced machines compile to the same
+//! code as before. `TextTracer` and `JsonTracer` write one line per event,
+//! either for people to read or, as JSON lines, for other tools to consume.
+
+use super::{Instructions, Word};
+use std::fmt::{self, Write as _};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// Receives an event for everything a machine does. Every hook does nothing
+/// by default, so implementations only override the ones they care about.
+pub trait Tracer<N = isize> {
+    /// An instruction was decoded at `ip` and is about to execute.
+    fn decode(&mut self, _ip: usize, _instruction: &Instructions<N>) {}
+
+    /// An operand was read from memory.
+    fn read(&mut self, _address: usize, _value: N) {}
+
+    /// An instruction wrote to memory.
+    fn write(&mut self, _address: usize, _value: N) {}
+
+    /// An input instruction consumed a value.
+    fn input(&mut self, _value: N) {}
+
+    /// An output instruction sent a value.
+    fn output(&mut self, _value: N) {}
+
+    /// A jump instruction at `from` was taken.
+    fn jump(&mut self, _from: usize, _to: usize) {}
+
+    /// The relative base was adjusted to `value`.
+    fn relative_base(&mut self, _value: N) {}
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoTracer;
+
+impl<N> Tracer<N> for NoTracer {}
+
+impl<N, T: Tracer<N> + ?Sized> Tracer<N> for &mut T {
+    fn decode(&mut self, ip: usize, instruction: &Instructions<N>) {
+        (**self).decode(ip, instruction)
+    }
+
+    fn read(&mut self, address: usize, value: N) {
+        (**self).read(address, value)
+    }
+
+    fn write(&mut self, address: usize, value: N) {
+        (**self).write(address, value)
+    }
+
+    fn input(&mut self, value: N) {
+        (**self).input(value)
+    }
+
+    fn output(&mut self, value: N) {
+        (**self).output(value)
+    }
+
+    fn jump(&mut self, from: usize, to: usize) {
+        (**self).jump(from, to)
+    }
+
+    fn relative_base(&mut self, value: N) {
+        (**self).relative_base(value)
+    }
+}
+
+impl<N, T: Tracer<N> + ?Sized> Tracer<N> for Box<T> {
+    fn decode(&mut self, ip: usize, instruction: &Instructions<N>) {
+        (**self).decode(ip, instruction)
+    }
+
+    fn read(&mut self, address: usize, value: N) {
+        (**self).read(address, value)
+    }
+
+    fn write(&mut self, address: usize, value: N) {
+        (**self).write(address, value)
+    }
+
+    fn input(&mut self, value: N) {
+        (**self).input(value)
+    }
+
+    fn output(&mut self, value: N) {
+        (**self).output(value)
+    }
+
+    fn jump(&mut self, from: usize, to: usize) {
+        (**self).jump(from, to)
+    }
+
+    fn relative_base(&mut self, value: N) {
+        (**self).relative_base(value)
+    }
+}
+
+/// Passes every event to both tracers, e.g. to profile a run while also
+/// writing a trace of it.
+impl<N: Clone, A: Tracer<N>, B: Tracer<N>> Tracer<N> for (A, B) {
+    fn decode(&mut self, ip: usize, instruction: &Instructions<N>) {
+        self.0.decode(ip, instruction);
+        self.1.decode(ip, instruction);
+    }
+
+    fn read(&mut self, address: usize, value: N) {
+        self.0.read(address, value.clone());
+        self.1.read(address, value);
+    }
+
+    fn write(&mut self, address: usize, value: N) {
+        self.0.write(address, value.clone());
+        self.1.write(address, value);
+    }
+
+    fn input(&mut self, value: N) {
+        self.0.input(value.clone());
+        self.1.input(value);
+    }
+
+    fn output(&mut self, value: N) {
+        self.0.output(value.clone());
+        self.1.output(value);
+    }
+
+    fn jump(&mut self, from: usize, to: usize) {
+        self.0.jump(from, to);
+        self.1.jump(from, to);
+    }
+
+    fn relative_base(&mut self, value: N) {
+        self.0.relative_base(value.clone());
+        self.1.relative_base(value);
+    }
+}
+
+/// Writes events to a writer, holding on to the first error so the hooks
+/// themselves never fail.
+struct LineWriter<W: Write> {
+    writer: W,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> LineWriter<W> {
+    fn new(writer: W) -> Self {
+        Self { writer, error: None }
+    }
+
+    fn line(&mut self, args: std::fmt::Arguments<'_>) {
+        if self.error.is_none() {
+            if let Err(e) = self.writer.write_fmt(args).and_then(|_| self.writer.write_all(b"\n")) {
+                self.error = Some(e);
+            }
+        }
+    }
+
+    fn into_inner(mut self) -> io::Result<W> {
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => self.writer.flush().map(|_| self.writer),
+        }
+    }
+}
+
+/// Traces every event as a line of plain text, e.g. `write 100 = 5`.
+pub struct TextTracer<W: Write> {
+    out: LineWriter<W>,
+}
+
+impl<W: Write> TextTracer<W> {
+    pub fn new(writer: W) -> Self {
+        Self { out: LineWriter::new(writer) }
+    }
+
+    /// Flushes the trace and returns the writer, or the first error that
+    /// happened while writing it.
+    pub fn into_inner(self) -> io::Result<W> {
+        self.out.into_inner()
+    }
+}
+
+impl TextTracer<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write, N: Word> Tracer<N> for TextTracer<W> {
+    fn decode(&mut self, ip: usize, instruction: &Instructions<N>) {
+        self.out.line(format_args!("{:>5}: {}", ip, instruction))
+    }
+
+    fn read(&mut self, address: usize, value: N) {
+        self.out.line(format_args!("       read {} = {}", address, value))
+    }
+
+    fn write(&mut self, address: usize, value: N) {
+        self.out.line(format_args!("       write {} = {}", address, value))
+    }
+
+    fn input(&mut self, value: N) {
+        self.out.line(format_args!("       input {}", value))
+    }
+
+    fn output(&mut self, value: N) {
+        self.out.line(format_args!("       output {}", value))
+    }
+
+    fn jump(&mut self, from: usize, to: usize) {
+        self.out.line(format_args!("       jump {} -> {}", from, to))
+    }
+
+    fn relative_base(&mut self, value: N) {
+        self.out.line(format_args!("       relative_base = {}", value))
+    }
+}
+
+/// Displays the wrapped value as the inside of a JSON string, escaping
+/// quotes, backslashes and control characters. Custom opcodes can have any
+/// mnemonic, so instructions aren't safe to write unescaped.
+pub(crate) struct JsonStr<T>(pub(crate) T);
+
+impl<T: fmt::Display> fmt::Display for JsonStr<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for c in self.0.to_string().chars() {
+            match c {
+                '"' => f.write_str("\\\"")?,
+                '\\' => f.write_str("\\\\")?,
+                c if c.is_control() => write!(f, "\\u{:04x}", c as u32)?,
+                c => f.write_char(c)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Traces every event as a JSON object on its own line, tagged with an
+/// `event` field.
+pub struct JsonTracer<W: Write> {
+    out: LineWriter<W>,
+}
+
+impl<W: Write> JsonTracer<W> {
+    pub fn new(writer: W) -> Self {
+        Self { out: LineWriter::new(writer) }
+    }
+
+    /// Flushes the trace and returns the writer, or the first error that
+    /// happened while writing it.
+    pub fn into_inner(self) -> io::Result<W> {
+        self.out.into_inner()
+    }
+}
+
+impl JsonTracer<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write, N: Word> Tracer<N> for JsonTracer<W> {
+    fn decode(&mut self, ip: usize, instruction: &Instructions<N>) {
+        self.out.line(format_args!(
+            r#"{{"event":"decode","ip":{},"instruction":"{}"}}"#,
+            ip,
+            JsonStr(instruction)
+        ))
+    }
+
+    fn read(&mut self, address: usize, value: N) {
+        self.out.line(format_args!(r#"{{"event":"read","address":{},"value":{}}}"#, address, value))
+    }
+
+    fn write(&mut self, address: usize, value: N) {
+        self.out
+            .line(format_args!(r#"{{"event":"write","address":{},"value":{}}}"#, address, value))
+    }
+
+    fn input(&mut self, value: N) {
+        self.out.line(format_args!(r#"{{"event":"input","value":{}}}"#, value))
+    }
+
+    fn output(&mut self, value: N) {
+        self.out.line(format_args!(r#"{{"event":"output","value":{}}}"#, value))
+    }
+
+    fn jump(&mut self, from: usize, to: usize) {
+        self.out.line(format_args!(r#"{{"event":"jump","from":{},"to":{}}}"#, from, to))
+    }
+
+    fn relative_base(&mut self, value: N) {
+        self.out.line(format_args!(r#"{{"event":"relative_base","value":{}}}"#, value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::{empty, IntcodeMachine};
+
+    #[test]
+    fn text_trace() {
+        let mut tracer = TextTracer::new(vec![]);
+        let mut machine =
+            IntcodeMachine::new(&[109, 5, 204, -3, 1105, 1, 7, 99], empty(), Vec::new())
+                .with_tracer(&mut tracer);
+        machine.run().unwrap();
+
+        let trace = String::from_utf8(tracer.into_inner().unwrap()).unwrap();
+        assert_eq!(
+            trace,
+            "    0: arel imm(5)
+       relative_base = 5
+    2: output rel(-3)
+       read 2 = 204
+       output 204
+    4: jit imm(1), imm(7)
+       jump 4 -> 7
+    7: halt
+"
+        );
+    }
+
+    #[test]
+    fn json_trace() {
+        let mut tracer = JsonTracer::new(vec![]);
+        let mut machine = IntcodeMachine::new(&[3, 0, 99], vec![42isize].into_iter(), Vec::new())
+            .with_tracer(&mut tracer);
+        machine.run().unwrap();
+
+        let trace = String::from_utf8(tracer.into_inner().unwrap()).unwrap();
+        assert_eq!(
+            trace.lines().collect::<Vec<_>>(),
+            vec![
+                r#"{"event":"decode","ip":0,"instruction":"input pos(0)"}"#,
+                r#"{"event":"input","value":42}"#,
+                r#"{"event":"write","address":0,"value":42}"#,
+                r#"{"event":"decode","ip":2,"instruction":"halt"}"#,
+            ]
+        );
+
+        assert_eq!(JsonStr("say \"hi\"\\\n").to_string(), r#"say \"hi\"\\\u000a"#);
+    }
+}
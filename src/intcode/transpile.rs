@@ -0,0 +1,371 @@
+//! Ahead-of-time translation of Intcode images into Rust source.
+//!
+//! The image is swept like the disassembler does and every instruction found
+//! becomes an arm of a `match ip` state machine, with its operand modes and
+//! constants baked in. The generated function has the same shape as
+//! `IntcodeMachine::run`: it takes a `Source` and a `Sink` and returns
+//! how the program ended.
+//!
+//! Anything the translation can't vouch for is handed to the interpreter:
+//! jumping to an address that wasn't transpiled, or writing into the
+//! transpiled code, ends the state machine and resumes an `IntcodeMachine`
+//! from the current memory and registers.
+//!
+//! Arithmetic traps on overflow, like an `IntcodeMachine` with the default
+//! `Overflow::Trap`.
+
+use super::disasm::{self, Line};
+use super::{Destination, Instructions, Operand};
+use std::fmt::Write;
+
+pub struct Transpiler<'a> {
+    program: &'a [isize],
+    name: String,
+    intcode_path: String,
+}
+
+impl<'a> Transpiler<'a> {
+    pub fn new(program: &'a [isize]) -> Self {
+        Self {
+            program,
+            name: String::from("run"),
+            intcode_path: String::from("advent_of_code_2019::intcode"),
+        }
+    }
+
+    /// Names the generated function, `run` by default.
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+
+    /// Path the generated code imports the `intcode` module from, which is
+    /// `advent_of_code_2019::intcode` by default.
+    pub fn with_intcode_path(mut self, path: &str) -> Self {
+        self.intcode_path = path.to_string();
+        self
+    }
+
+    pub fn transpile(&self) -> String {
+        let lines = disasm::disassemble(self.program);
+        let code: Vec<(usize, usize)> = lines
+            .iter()
+            .filter(|line| matches!(line, Line::Instruction { .. }))
+            .map(|line| (line.address(), line.address() + line.len()))
+            .collect();
+
+        let mut out = String::new();
+        let path = &self.intcode_path;
+
+        let _ = writeln!(out, "/// Transpiled from a {} word Intcode image.", self.program.len());
+        let _ = writeln!(
+            out,
+            "#[allow(dead_code, unreachable_code, unused_imports, unused_mut, clippy::all)]"
+        );
+        let _ = writeln!(out, "pub fn {0}<R: {1}::Source, W: {1}::Sink<isize>>(", self.name, path);
+        let _ = writeln!(out, "    mut input: R,");
+        let _ = writeln!(out, "    mut output: W,");
+        let _ = writeln!(out, ") -> Result<{0}::ExitReason, {0}::IntcodeError> {{", path);
+        let _ = writeln!(
+            out,
+            "    use {}::{{ErrorKind, ExitReason, IntcodeError, IntcodeMachine, MachineState, Memory, Sink, Source}};",
+            path
+        );
+        let _ = writeln!(out);
+        let _ = writeln!(out, "    const IMAGE: &[isize] = &[{}];", join(self.program));
+        let _ = writeln!(out);
+        let _ = writeln!(out, "    fn address(value: isize) -> Result<usize, ErrorKind> {{");
+        let _ = writeln!(out, "        if value < 0 {{");
+        let _ = writeln!(out, "            Err(ErrorKind::AddressOutOfBounds(value))");
+        let _ = writeln!(out, "        }} else {{");
+        let _ = writeln!(out, "            Ok(value as usize)");
+        let _ = writeln!(out, "        }}");
+        let _ = writeln!(out, "    }}");
+        let _ = writeln!(out);
+        let _ = writeln!(
+            out,
+            "    fn relative(rb: isize, offset: isize) -> Result<usize, ErrorKind> {{"
+        );
+        let _ =
+            writeln!(out, "        address(rb.checked_add(offset).ok_or(ErrorKind::Overflow)?)");
+        let _ = writeln!(out, "    }}");
+        let _ = writeln!(out);
+        let _ = writeln!(out, "    /// Whether `address` is part of a transpiled instruction.");
+        let _ = writeln!(out, "    fn is_code(address: usize) -> bool {{");
+        let _ = writeln!(out, "        match address {{");
+        for (start, end) in merge(&code) {
+            let _ = writeln!(out, "            {}..={} => true,", start, end - 1);
+        }
+        let _ = writeln!(out, "            _ => false,");
+        let _ = writeln!(out, "        }}");
+        let _ = writeln!(out, "    }}");
+        let _ = writeln!(out);
+        let _ = writeln!(out, "    let mut memory = Memory::new(IMAGE);");
+        let _ = writeln!(out, "    let mut ip = 0;");
+        let _ = writeln!(out, "    let mut rb = 0isize;");
+        let _ = writeln!(out);
+        let _ = writeln!(out, "    loop {{");
+        let _ = writeln!(out, "        match ip {{");
+
+        for line in &lines {
+            if let Line::Instruction { address, words, instruction } = line {
+                let is_code = |a: usize| code.iter().any(|&(start, end)| start <= a && a < end);
+                let body = arm(instruction, *address + words.len(), &is_code);
+
+                let _ = writeln!(out, "            {} => {{", address);
+                let _ = writeln!(out, "                // {}", instruction);
+                if body.iter().any(|line| line.contains("fault")) {
+                    let _ = writeln!(
+                        out,
+                        "                let fault = |kind| IntcodeError::new({}, {}, kind);",
+                        address, words[0]
+                    );
+                }
+                for line in body {
+                    let _ = writeln!(out, "                {}", line);
+                }
+                let _ = writeln!(out, "            }}");
+            }
+        }
+
+        let _ = writeln!(out, "            _ => break,");
+        let _ = writeln!(out, "        }}");
+        let _ = writeln!(out, "    }}");
+        let _ = writeln!(out);
+        let _ = writeln!(
+            out,
+            "    // `ip` is in code that wasn't transpiled or has since been overwritten."
+        );
+        let _ = writeln!(
+            out,
+            "    let state = MachineState {{ memory, ip, relative_base: rb, halted: false, pending: Vec::new() }};"
+        );
+        let _ = writeln!(out, "    IntcodeMachine::from_state(state, input, output).run()");
+        let _ = writeln!(out, "}}");
+
+        out
+    }
+}
+
+/// Transpiles `program` into a function called `name`.
+pub fn transpile(program: &[isize], name: &str) -> String {
+    Transpiler::new(program).with_name(name).transpile()
+}
+
+/// The statements for one instruction, which end with `ip` pointing at the
+/// next one to run.
+fn arm(instruction: &Instructions, next: usize, is_code: &dyn Fn(usize) -> bool) -> Vec<String> {
+    let mut body = Vec::new();
+
+    match instruction {
+        Instructions::Add(i) => {
+            body.push(format!("let a = {};", load(i.op1)));
+            body.push(format!("let b = {};", load(i.op2)));
+            body.push(format!("let v = {};", checked("a", "add", "b")));
+            store(&mut body, i.dst, "v", next, is_code);
+        }
+        Instructions::Mul(i) => {
+            body.push(format!("let a = {};", load(i.op1)));
+            body.push(format!("let b = {};", load(i.op2)));
+            body.push(format!("let v = {};", checked("a", "mul", "b")));
+            store(&mut body, i.dst, "v", next, is_code);
+        }
+        Instructions::LessThan(i) => {
+            let d = destination(&mut body, i.dst);
+            body.push(format!("let a = {};", load(i.op1)));
+            body.push(format!("let b = {};", load(i.op2)));
+            write(&mut body, d, "(a < b) as isize", next, is_code);
+        }
+        Instructions::EqualTo(i) => {
+            let d = destination(&mut body, i.dst);
+            body.push(format!("let a = {};", load(i.op1)));
+            body.push(format!("let b = {};", load(i.op2)));
+            write(&mut body, d, "(a == b) as isize", next, is_code);
+        }
+        Instructions::JumpIfTrue(i) => jump(&mut body, i.test, i.jump_to, "!=", next),
+        Instructions::JumpIfFalse(i) => jump(&mut body, i.test, i.jump_to, "==", next),
+        Instructions::Input(i) => {
+            let d = destination(&mut body, i.operand);
+            body.push(String::from(
+                "let a = input.recv().value().ok_or(ErrorKind::InputExhausted).map_err(fault)?;",
+            ));
+            write(&mut body, d, "a", next, is_code);
+        }
+        Instructions::Output(i) => {
+            body.push(format!("let a = {};", load(i.operand)));
+            body.push(String::from("output.send(a);"));
+            body.push(format!("ip = {};", next));
+        }
+        Instructions::ModRelBase(i) => {
+            body.push(format!("let a = {};", load(i.operand)));
+            body.push(format!("rb = {};", checked("rb", "add", "a")));
+            body.push(format!("ip = {};", next));
+        }
+        Instructions::Halt(_) => body.push(String::from("return Ok(ExitReason::Halted);")),
+        // Leaves `ip` on the instruction for the interpreter.
+        Instructions::Extension(_) => body.push(String::from("break;")),
+    }
+
+    body
+}
+
+fn load(operand: Operand) -> String {
+    match operand {
+        Operand::Immediate(n) => n.to_string(),
+        Operand::Position(p) => format!("memory.read({}).map_err(fault)?", p),
+        Operand::Relative(r) => {
+            format!("memory.read(relative(rb, {}).map_err(fault)?).map_err(fault)?", r)
+        }
+    }
+}
+
+/// Where a destination points, either known now or computed into `d`.
+enum Target {
+    Known(usize),
+    Computed,
+}
+
+fn destination(body: &mut Vec<String>, dst: Destination) -> Target {
+    match dst {
+        Destination::Position(p) => Target::Known(p),
+        Destination::Relative(r) => {
+            body.push(format!("let d = relative(rb, {}).map_err(fault)?;", r));
+            Target::Computed
+        }
+    }
+}
+
+fn store(
+    body: &mut Vec<String>,
+    dst: Destination,
+    value: &str,
+    next: usize,
+    is_code: &dyn Fn(usize) -> bool,
+) {
+    let d = destination(body, dst);
+    write(body, d, value, next, is_code);
+}
+
+fn write(
+    body: &mut Vec<String>,
+    d: Target,
+    value: &str,
+    next: usize,
+    is_code: &dyn Fn(usize) -> bool,
+) {
+    match d {
+        Target::Known(p) => {
+            body.push(format!("memory.write({}, {}).map_err(fault)?;", p, value));
+            body.push(format!("ip = {};", next));
+            if is_code(p) {
+                body.push(String::from("break;"));
+            }
+        }
+        Target::Computed => {
+            body.push(format!("memory.write(d, {}).map_err(fault)?;", value));
+            body.push(format!("ip = {};", next));
+            body.push(String::from("if is_code(d) {"));
+            body.push(String::from("    break;"));
+            body.push(String::from("}"));
+        }
+    }
+}
+
+fn jump(body: &mut Vec<String>, test: Operand, jump_to: Operand, op: &str, next: usize) {
+    let target = match jump_to {
+        Operand::Immediate(n) if n >= 0 => n.to_string(),
+        _ => format!("address({}).map_err(fault)?", load(jump_to)),
+    };
+
+    body.push(format!("let a = {};", load(test)));
+    body.push(format!("ip = if a {} 0 {{ {} }} else {{ {} }};", op, target, next));
+}
+
+/// `a op b`, faulting if it overflows.
+fn checked(a: &str, op: &str, b: &str) -> String {
+    format!("isize::checked_{}({}, {}).ok_or(ErrorKind::Overflow).map_err(fault)?", op, a, b)
+}
+
+/// Joins adjacent `start..end` ranges.
+fn merge(ranges: &[(usize, usize)]) -> Vec<(usize, usize)> {
+    let mut merged: Vec<(usize, usize)> = Vec::new();
+
+    for &(start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if last.1 == start => last.1 = end,
+            _ => merged.push((start, end)),
+        }
+    }
+
+    merged
+}
+
+fn join(words: &[isize]) -> String {
+    words.iter().map(isize::to_string).collect::<Vec<_>>().join(", ")
+}
+
+#[cfg(test)]
+#[rustfmt::skip]
+mod fixture;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::{empty, ExitReason, IntcodeError, IntcodeMachine};
+
+    const PROGRAMS: &[(&str, &str)] = &[
+        ("quine", "109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99"),
+        (
+            "compare_to_8",
+            "3,21,1008,21,8,20,1005,20,22,107,8,21,20,1006,20,31,1106,0,36,98,0,0,1002,21,125,20,\
+             4,20,1105,1,46,104,999,1105,1,46,1101,1000,1,20,4,20,1105,1,46,98,99",
+        ),
+        ("self_modifying", "1,9,10,3,2,3,11,0,99,30,40,50"),
+    ];
+
+    fn parse(program: &str) -> Vec<isize> {
+        program.split(',').map(|n| n.parse().unwrap()).collect()
+    }
+
+    /// `fixture.rs` holds the output for `PROGRAMS`, so it can be compiled
+    /// and run against the interpreter below.
+    #[test]
+    fn fixture_is_current() {
+        let fixture = include_str!("transpile/fixture.rs");
+
+        for (name, program) in PROGRAMS {
+            let source = Transpiler::new(&parse(program))
+                .with_name(name)
+                .with_intcode_path("crate::intcode")
+                .transpile();
+            assert!(fixture.contains(&source), "{}", source);
+        }
+    }
+
+    #[test]
+    fn matches_interpreter() {
+        let interpret = |program: &str, input: &[isize]| {
+            let mut output = Vec::new();
+            let input = input.iter().copied();
+            let result = IntcodeMachine::new(&parse(program), input, &mut output).run();
+            (result, output)
+        };
+        let compiled = |run: &dyn Fn(&mut Vec<isize>) -> Result<ExitReason, IntcodeError>| {
+            let mut output = Vec::new();
+            let result = run(&mut output);
+            (result, output)
+        };
+
+        assert_eq!(compiled(&|out| fixture::quine(empty(), out)), interpret(PROGRAMS[0].1, &[]));
+        for input in &[&[7][..], &[8], &[9], &[]] {
+            assert_eq!(
+                compiled(&|out| fixture::compare_to_8(input.iter().copied(), out)),
+                interpret(PROGRAMS[1].1, input)
+            );
+        }
+        assert_eq!(
+            compiled(&|out| fixture::self_modifying(empty(), out)),
+            interpret(PROGRAMS[2].1, &[])
+        );
+    }
+}
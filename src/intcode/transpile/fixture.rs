@@ -0,0 +1,302 @@
+//! Output of `Transpiler` for `tests::PROGRAMS`, compiled in so the
+//! tests can check it against the interpreter. `fixture_is_current` prints
+//! the new source for any function that no longer matches.
+
+/// Transpiled from a 16 word Intcode image.
+#[allow(dead_code, unreachable_code, unused_imports, unused_mut, clippy::all)]
+pub fn quine<R: crate::intcode::Source, W: crate::intcode::Sink<isize>>(
+    mut input: R,
+    mut output: W,
+) -> Result<crate::intcode::ExitReason, crate::intcode::IntcodeError> {
+    use crate::intcode::{ErrorKind, ExitReason, IntcodeError, IntcodeMachine, MachineState, Memory, Sink, Source};
+
+    const IMAGE: &[isize] = &[109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99];
+
+    fn address(value: isize) -> Result<usize, ErrorKind> {
+        if value < 0 {
+            Err(ErrorKind::AddressOutOfBounds(value))
+        } else {
+            Ok(value as usize)
+        }
+    }
+
+    fn relative(rb: isize, offset: isize) -> Result<usize, ErrorKind> {
+        address(rb.checked_add(offset).ok_or(ErrorKind::Overflow)?)
+    }
+
+    /// Whether `address` is part of a transpiled instruction.
+    fn is_code(address: usize) -> bool {
+        match address {
+            0..=15 => true,
+            _ => false,
+        }
+    }
+
+    let mut memory = Memory::new(IMAGE);
+    let mut ip = 0;
+    let mut rb = 0isize;
+
+    loop {
+        match ip {
+            0 => {
+                // arel imm(1)
+                let fault = |kind| IntcodeError::new(0, 109, kind);
+                let a = 1;
+                rb = isize::checked_add(rb, a).ok_or(ErrorKind::Overflow).map_err(fault)?;
+                ip = 2;
+            }
+            2 => {
+                // output rel(-1)
+                let fault = |kind| IntcodeError::new(2, 204, kind);
+                let a = memory.read(relative(rb, -1).map_err(fault)?).map_err(fault)?;
+                output.send(a);
+                ip = 4;
+            }
+            4 => {
+                // add pos(100), imm(1), pos(100)
+                let fault = |kind| IntcodeError::new(4, 1001, kind);
+                let a = memory.read(100).map_err(fault)?;
+                let b = 1;
+                let v = isize::checked_add(a, b).ok_or(ErrorKind::Overflow).map_err(fault)?;
+                memory.write(100, v).map_err(fault)?;
+                ip = 8;
+            }
+            8 => {
+                // eq pos(100), imm(16), pos(101)
+                let fault = |kind| IntcodeError::new(8, 1008, kind);
+                let a = memory.read(100).map_err(fault)?;
+                let b = 16;
+                memory.write(101, (a == b) as isize).map_err(fault)?;
+                ip = 12;
+            }
+            12 => {
+                // jif pos(101), imm(0)
+                let fault = |kind| IntcodeError::new(12, 1006, kind);
+                let a = memory.read(101).map_err(fault)?;
+                ip = if a == 0 { 0 } else { 15 };
+            }
+            15 => {
+                // halt
+                return Ok(ExitReason::Halted);
+            }
+            _ => break,
+        }
+    }
+
+    // `ip` is in code that wasn't transpiled or has since been overwritten.
+    let state = MachineState { memory, ip, relative_base: rb, halted: false, pending: Vec::new() };
+    IntcodeMachine::from_state(state, input, output).run()
+}
+
+/// Transpiled from a 47 word Intcode image.
+#[allow(dead_code, unreachable_code, unused_imports, unused_mut, clippy::all)]
+pub fn compare_to_8<R: crate::intcode::Source, W: crate::intcode::Sink<isize>>(
+    mut input: R,
+    mut output: W,
+) -> Result<crate::intcode::ExitReason, crate::intcode::IntcodeError> {
+    use crate::intcode::{ErrorKind, ExitReason, IntcodeError, IntcodeMachine, MachineState, Memory, Sink, Source};
+
+    const IMAGE: &[isize] = &[3, 21, 1008, 21, 8, 20, 1005, 20, 22, 107, 8, 21, 20, 1006, 20, 31, 1106, 0, 36, 98, 0, 0, 1002, 21, 125, 20, 4, 20, 1105, 1, 46, 104, 999, 1105, 1, 46, 1101, 1000, 1, 20, 4, 20, 1105, 1, 46, 98, 99];
+
+    fn address(value: isize) -> Result<usize, ErrorKind> {
+        if value < 0 {
+            Err(ErrorKind::AddressOutOfBounds(value))
+        } else {
+            Ok(value as usize)
+        }
+    }
+
+    fn relative(rb: isize, offset: isize) -> Result<usize, ErrorKind> {
+        address(rb.checked_add(offset).ok_or(ErrorKind::Overflow)?)
+    }
+
+    /// Whether `address` is part of a transpiled instruction.
+    fn is_code(address: usize) -> bool {
+        match address {
+            0..=18 => true,
+            22..=44 => true,
+            46..=46 => true,
+            _ => false,
+        }
+    }
+
+    let mut memory = Memory::new(IMAGE);
+    let mut ip = 0;
+    let mut rb = 0isize;
+
+    loop {
+        match ip {
+            0 => {
+                // input pos(21)
+                let fault = |kind| IntcodeError::new(0, 3, kind);
+                let a = input.recv().value().ok_or(ErrorKind::InputExhausted).map_err(fault)?;
+                memory.write(21, a).map_err(fault)?;
+                ip = 2;
+            }
+            2 => {
+                // eq pos(21), imm(8), pos(20)
+                let fault = |kind| IntcodeError::new(2, 1008, kind);
+                let a = memory.read(21).map_err(fault)?;
+                let b = 8;
+                memory.write(20, (a == b) as isize).map_err(fault)?;
+                ip = 6;
+            }
+            6 => {
+                // jit pos(20), imm(22)
+                let fault = |kind| IntcodeError::new(6, 1005, kind);
+                let a = memory.read(20).map_err(fault)?;
+                ip = if a != 0 { 22 } else { 9 };
+            }
+            9 => {
+                // lt imm(8), pos(21), pos(20)
+                let fault = |kind| IntcodeError::new(9, 107, kind);
+                let a = 8;
+                let b = memory.read(21).map_err(fault)?;
+                memory.write(20, (a < b) as isize).map_err(fault)?;
+                ip = 13;
+            }
+            13 => {
+                // jif pos(20), imm(31)
+                let fault = |kind| IntcodeError::new(13, 1006, kind);
+                let a = memory.read(20).map_err(fault)?;
+                ip = if a == 0 { 31 } else { 16 };
+            }
+            16 => {
+                // jif imm(0), imm(36)
+                let a = 0;
+                ip = if a == 0 { 36 } else { 19 };
+            }
+            22 => {
+                // mul pos(21), imm(125), pos(20)
+                let fault = |kind| IntcodeError::new(22, 1002, kind);
+                let a = memory.read(21).map_err(fault)?;
+                let b = 125;
+                let v = isize::checked_mul(a, b).ok_or(ErrorKind::Overflow).map_err(fault)?;
+                memory.write(20, v).map_err(fault)?;
+                ip = 26;
+            }
+            26 => {
+                // output pos(20)
+                let fault = |kind| IntcodeError::new(26, 4, kind);
+                let a = memory.read(20).map_err(fault)?;
+                output.send(a);
+                ip = 28;
+            }
+            28 => {
+                // jit imm(1), imm(46)
+                let a = 1;
+                ip = if a != 0 { 46 } else { 31 };
+            }
+            31 => {
+                // output imm(999)
+                let a = 999;
+                output.send(a);
+                ip = 33;
+            }
+            33 => {
+                // jit imm(1), imm(46)
+                let a = 1;
+                ip = if a != 0 { 46 } else { 36 };
+            }
+            36 => {
+                // add imm(1000), imm(1), pos(20)
+                let fault = |kind| IntcodeError::new(36, 1101, kind);
+                let a = 1000;
+                let b = 1;
+                let v = isize::checked_add(a, b).ok_or(ErrorKind::Overflow).map_err(fault)?;
+                memory.write(20, v).map_err(fault)?;
+                ip = 40;
+            }
+            40 => {
+                // output pos(20)
+                let fault = |kind| IntcodeError::new(40, 4, kind);
+                let a = memory.read(20).map_err(fault)?;
+                output.send(a);
+                ip = 42;
+            }
+            42 => {
+                // jit imm(1), imm(46)
+                let a = 1;
+                ip = if a != 0 { 46 } else { 45 };
+            }
+            46 => {
+                // halt
+                return Ok(ExitReason::Halted);
+            }
+            _ => break,
+        }
+    }
+
+    // `ip` is in code that wasn't transpiled or has since been overwritten.
+    let state = MachineState { memory, ip, relative_base: rb, halted: false, pending: Vec::new() };
+    IntcodeMachine::from_state(state, input, output).run()
+}
+
+/// Transpiled from a 12 word Intcode image.
+#[allow(dead_code, unreachable_code, unused_imports, unused_mut, clippy::all)]
+pub fn self_modifying<R: crate::intcode::Source, W: crate::intcode::Sink<isize>>(
+    mut input: R,
+    mut output: W,
+) -> Result<crate::intcode::ExitReason, crate::intcode::IntcodeError> {
+    use crate::intcode::{ErrorKind, ExitReason, IntcodeError, IntcodeMachine, MachineState, Memory, Sink, Source};
+
+    const IMAGE: &[isize] = &[1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50];
+
+    fn address(value: isize) -> Result<usize, ErrorKind> {
+        if value < 0 {
+            Err(ErrorKind::AddressOutOfBounds(value))
+        } else {
+            Ok(value as usize)
+        }
+    }
+
+    fn relative(rb: isize, offset: isize) -> Result<usize, ErrorKind> {
+        address(rb.checked_add(offset).ok_or(ErrorKind::Overflow)?)
+    }
+
+    /// Whether `address` is part of a transpiled instruction.
+    fn is_code(address: usize) -> bool {
+        match address {
+            0..=8 => true,
+            _ => false,
+        }
+    }
+
+    let mut memory = Memory::new(IMAGE);
+    let mut ip = 0;
+    let mut rb = 0isize;
+
+    loop {
+        match ip {
+            0 => {
+                // add pos(9), pos(10), pos(3)
+                let fault = |kind| IntcodeError::new(0, 1, kind);
+                let a = memory.read(9).map_err(fault)?;
+                let b = memory.read(10).map_err(fault)?;
+                let v = isize::checked_add(a, b).ok_or(ErrorKind::Overflow).map_err(fault)?;
+                memory.write(3, v).map_err(fault)?;
+                ip = 4;
+                break;
+            }
+            4 => {
+                // mul pos(3), pos(11), pos(0)
+                let fault = |kind| IntcodeError::new(4, 2, kind);
+                let a = memory.read(3).map_err(fault)?;
+                let b = memory.read(11).map_err(fault)?;
+                let v = isize::checked_mul(a, b).ok_or(ErrorKind::Overflow).map_err(fault)?;
+                memory.write(0, v).map_err(fault)?;
+                ip = 8;
+                break;
+            }
+            8 => {
+                // halt
+                return Ok(ExitReason::Halted);
+            }
+            _ => break,
+        }
+    }
+
+    // `ip` is in code that wasn't transpiled or has since been overwritten.
+    let state = MachineState { memory, ip, relative_base: rb, halted: false, pending: Vec::new() };
+    IntcodeMachine::from_state(state, input, output).run()
+}
@@ -0,0 +1,217 @@
+//! The integer types a machine can compute with.
+//!
+//! `IntcodeMachine` is generic over its word type, defaulting to `isize`.
+//! Programs whose values don't fit in one can run on `i64`, `i128`, or with
+//! the `num-bigint` feature `BigInt`, which never overflows. Addresses still
+//! have to fit in a `usize`.
+
+use std::convert::TryFrom;
+use std::fmt;
+
+/// An integer type an `IntcodeMachine` can compute with.
+pub trait Word: Clone + Ord + fmt::Debug + fmt::Display + 'static {
+    /// Zero, borrowed for as long as needed so memory can hand out a
+    /// reference to a cell that was never written.
+    const ZERO: &'static Self;
+
+    fn from_isize(n: isize) -> Self;
+    /// `None` if the value doesn't fit.
+    fn to_isize(&self) -> Option<isize>;
+    fn checked_add(&self, other: &Self) -> Option<Self>;
+    fn checked_mul(&self, other: &Self) -> Option<Self>;
+    fn wrapping_add(&self, other: &Self) -> Self;
+    fn wrapping_mul(&self, other: &Self) -> Self;
+
+    fn is_zero(&self) -> bool {
+        self == Self::ZERO
+    }
+
+    /// The value clamped to the `isize` range, for error reports.
+    fn saturate(&self) -> isize {
+        self.to_isize().unwrap_or(if self < Self::ZERO { isize::MIN } else { isize::MAX })
+    }
+}
+
+macro_rules! impl_word {
+    ($($t:ty),*) => {$(
+        impl Word for $t {
+            const ZERO: &'static Self = &0;
+
+            #[inline]
+            fn from_isize(n: isize) -> Self {
+                n as $t
+            }
+
+            #[inline]
+            fn to_isize(&self) -> Option<isize> {
+                isize::try_from(*self).ok()
+            }
+
+            #[inline]
+            fn checked_add(&self, other: &Self) -> Option<Self> {
+                <$t>::checked_add(*self, *other)
+            }
+
+            #[inline]
+            fn checked_mul(&self, other: &Self) -> Option<Self> {
+                <$t>::checked_mul(*self, *other)
+            }
+
+            #[inline]
+            fn wrapping_add(&self, other: &Self) -> Self {
+                <$t>::wrapping_add(*self, *other)
+            }
+
+            #[inline]
+            fn wrapping_mul(&self, other: &Self) -> Self {
+                <$t>::wrapping_mul(*self, *other)
+            }
+        }
+    )*};
+}
+
+impl_word!(isize, i64, i128);
+
+#[cfg(feature = "num-bigint")]
+impl Word for num_bigint::BigInt {
+    const ZERO: &'static Self = &num_bigint::BigInt::ZERO;
+
+    fn from_isize(n: isize) -> Self {
+        n.into()
+    }
+
+    fn to_isize(&self) -> Option<isize> {
+        isize::try_from(self).ok()
+    }
+
+    fn checked_add(&self, other: &Self) -> Option<Self> {
+        Some(self + other)
+    }
+
+    fn checked_mul(&self, other: &Self) -> Option<Self> {
+        Some(self * other)
+    }
+
+    fn wrapping_add(&self, other: &Self) -> Self {
+        self + other
+    }
+
+    fn wrapping_mul(&self, other: &Self) -> Self {
+        self * other
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "num-bigint")]
+    use crate::intcode::asm;
+    use crate::intcode::fixture::run_on_every_engine;
+    use crate::intcode::{
+        empty, ErrorKind, ExitReason, IntcodeError, IntcodeMachine, Memory, Overflow,
+    };
+
+    /// Squares its input seven times.
+    #[cfg(feature = "num-bigint")]
+    const SQUARE_REPEATEDLY: &str = "
+        input pos(x)
+loop:   mul pos(x), pos(x), pos(x)
+        add pos(n), imm(-1), pos(n)
+        jit pos(n), imm(loop)
+        output pos(x)
+        halt
+n:      .data 7
+x:      .data 0
+";
+
+    /// Squares 3037000500, which just overflows an `i64`.
+    const SQUARE: [isize; 8] = [1102, 3_037_000_500, 3_037_000_500, 7, 4, 7, 99, 0];
+
+    fn widen<N: Word>(program: &[isize]) -> Vec<N> {
+        program.iter().map(|&n| N::from_isize(n)).collect()
+    }
+
+    #[test]
+    fn large_numbers() {
+        let program = widen::<i64>(&[104, 1_125_899_906_842_624, 99]);
+        let mut machine = IntcodeMachine::new(&program, std::iter::empty(), Vec::new());
+        machine.run().unwrap();
+        assert_eq!(machine.output_mut(), &[1_125_899_906_842_624]);
+
+        let program = widen::<i64>(&[1102, 34_915_192, 34_915_192, 7, 4, 7, 99, 0]);
+        let mut machine = IntcodeMachine::new(&program, std::iter::empty(), Vec::new());
+        machine.run().unwrap();
+        assert_eq!(machine.output_mut(), &[1_219_070_632_396_864]);
+    }
+
+    #[test]
+    fn trap_on_overflow() {
+        let program = widen::<i64>(&SQUARE);
+        let (result, _) =
+            run_on_every_engine(|| IntcodeMachine::new(&program, std::iter::empty(), Vec::new()));
+        assert_eq!(result, Err(IntcodeError::new(0, 1102, ErrorKind::Overflow)));
+
+        let result = run_on_every_engine(|| {
+            IntcodeMachine::new(&program, std::iter::empty(), Vec::new())
+                .with_overflow(Overflow::Wrap)
+        });
+        let square = 3_037_000_500i64.wrapping_mul(3_037_000_500);
+        assert_eq!(result, (Ok(ExitReason::Halted), vec![square]));
+
+        let program = widen::<i128>(&SQUARE);
+        let result =
+            run_on_every_engine(|| IntcodeMachine::new(&program, std::iter::empty(), Vec::new()));
+        assert_eq!(result, (Ok(ExitReason::Halted), vec![9_223_372_037_000_250_000]));
+
+        let mut output = Vec::new();
+        let narrow = IntcodeMachine::new(&SQUARE, empty(), &mut output).run();
+        assert_eq!(narrow.unwrap_err().kind, ErrorKind::Overflow);
+    }
+
+    #[test]
+    fn same_as_isize() {
+        // Day 9's quine, which uses relative mode throughout.
+        let quine = [109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99];
+
+        let program = widen::<i128>(&quine);
+        let result =
+            run_on_every_engine(|| IntcodeMachine::new(&program, std::iter::empty(), Vec::new()));
+        assert_eq!(result, (Ok(ExitReason::Halted), program));
+    }
+
+    #[test]
+    fn addresses_fit_in_a_usize() {
+        let program = widen::<i128>(&[204, 0, 99]);
+        let mut memory = Memory::new(&program);
+        memory.write(1, i128::MAX).unwrap();
+
+        let mut machine = IntcodeMachine::with_memory(memory, std::iter::empty(), Vec::new());
+        assert_eq!(
+            machine.run(),
+            Err(IntcodeError::new(0, 204, ErrorKind::AddressOutOfBounds(isize::MAX)))
+        );
+
+        let program = [i128::MIN, 99];
+        let mut machine = IntcodeMachine::new(&program, std::iter::empty(), Vec::new());
+        assert_eq!(
+            machine.run(),
+            Err(IntcodeError::new(0, isize::MIN, ErrorKind::InvalidOpcode(isize::MIN)))
+        );
+    }
+
+    #[cfg(feature = "num-bigint")]
+    #[test]
+    fn bigint_never_overflows() {
+        use num_bigint::BigInt;
+
+        let program = asm::assemble(SQUARE_REPEATEDLY).unwrap();
+        let mut machine = IntcodeMachine::new(&widen::<i128>(&program), vec![3].into_iter(), ());
+        assert_eq!(machine.run().unwrap_err().kind, ErrorKind::Overflow);
+
+        let program = widen::<BigInt>(&program);
+        let result = run_on_every_engine(|| {
+            IntcodeMachine::new(&program, vec![BigInt::from(3)].into_iter(), Vec::new())
+        });
+        assert_eq!(result, (Ok(ExitReason::Halted), vec![BigInt::from(3).pow(128)]));
+    }
+}
@@ -1,38 +1,336 @@
-#![allow(clippy::zero_prefixed_literal)]
-
 use advent_of_code_2019::intcode::*;
-use std::io::{stdin, stdout, BufRead};
+use std::convert::TryFrom;
+use std::io::{stdin, stdout, BufRead, Stdin, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const USAGE: &str = "\
+usage: programmer [--resume <snapshot>] [--save <snapshot>] [--text]
+                  [--trace <file> | --trace-json <file>] [--profile <file>]
+       programmer asm <source>
+       programmer disasm <program>
+       programmer transpile <program> [name]
+       programmer cfg <program>
+       programmer adventure <program>
+       programmer script <program>";
+
+/// The opcode `script` puts host calls under.
+const HOST_OP: usize = 80;
+
+const PROGRAM: &str = "
+        jit pos(0), imm(start)
+.org 512
+start:  add imm(0), imm(0), pos(0)
+        add imm(0), imm(0), pos(2)
+read:   input pos(1000)
+        add imm(0), pos(1000), rel(0)
+        arel imm(1)
+        lt pos(1000), imm(99999), pos(1001)
+        jif pos(1001), imm(0)
+        jit pos(1001), imm(read)
+";
 
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    match args.first().map(String::as_str) {
+        Some("asm") => asm(&args[1..]),
+        Some("disasm") => disasm(&args[1..]),
+        Some("transpile") => transpile(&args[1..]),
+        Some("cfg") => cfg(&args[1..]),
+        Some("adventure") => adventure(&args[1..]),
+        Some("script") => script(&args[1..]),
+        _ => run(&args),
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    std::process::exit(1);
+}
+
+fn load_program(path: &str) -> Vec<isize> {
+    let text = std::fs::read_to_string(path).unwrap_or_else(|e| {
+        eprintln!("couldn't read {}: {}", path, e);
+        std::process::exit(1);
+    });
+
+    text.trim()
+        .split(',')
+        .map(|n| n.trim().parse())
+        .collect::<Result<_, _>>()
+        .unwrap_or_else(|e| {
+            eprintln!("couldn't parse {}: {}", path, e);
+            std::process::exit(1);
+        })
+}
+
+fn asm(args: &[String]) {
+    let path = match args {
+        [path] => path,
+        _ => usage(),
+    };
+
+    let source = std::fs::read_to_string(path).unwrap_or_else(|e| {
+        eprintln!("couldn't read {}: {}", path, e);
+        std::process::exit(1);
+    });
+
+    match asm::assemble(&source) {
+        Ok(program) => {
+            println!(
+                "{}",
+                program
+                    .iter()
+                    .map(isize::to_string)
+                    .collect::<Vec<_>>()
+                    .join(",")
+            )
+        }
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn disasm(args: &[String]) {
+    match args {
+        [path] => print!("{}", disasm::listing(&load_program(path))),
+        _ => usage(),
+    }
+}
+
+fn transpile(args: &[String]) {
+    match args {
+        [path] => print!("{}", transpile::transpile(&load_program(path), "run")),
+        [path, name] => print!("{}", transpile::transpile(&load_program(path), name)),
+        _ => usage(),
+    }
+}
+
+fn cfg(args: &[String]) {
+    match args {
+        [path] => print!(
+            "{}",
+            cfg::ControlFlowGraph::build(&load_program(path)).to_dot()
+        ),
+        _ => usage(),
+    }
+}
+
+/// Runs an ASCII program interactively, with each line typed on stdin sent
+/// as a command and any non-ASCII output printed as a number.
+fn adventure(args: &[String]) {
+    let path = match args {
+        [path] => path,
+        _ => usage(),
+    };
+
+    let stdin = stdin();
+    let input = ascii::AsciiSource::new(typed_lines(&stdin));
+
+    interact(IntcodeMachine::new(&load_program(path), input, ()));
+}
+
+/// Runs a program like `adventure` does, with host calls under opcode 80:
+///
+/// - 0: prints the string at `args`, returning its length.
+/// - 1: returns the milliseconds since the Unix epoch.
+/// - 2: returns a pseudo-random number below argument 0.
+/// - 3: reads the file named by the string at argument 0 into memory from
+///   argument 1, returning its length or -1 if it can't be read.
+///
+/// Strings are ASCII codes ending in a zero.
+fn script(args: &[String]) {
+    let path = match args {
+        [path] => path,
+        _ => usage(),
+    };
+
+    let stdin = stdin();
+    let input = ascii::AsciiSource::new(typed_lines(&stdin));
+    let machine = IntcodeMachine::new(&load_program(path), input, ()).with_host(HOST_OP, host());
+
+    interact(machine);
+}
+
+fn host() -> host::Host {
+    let now = || {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+    };
+    let address =
+        |value: isize| usize::try_from(value).map_err(|_| ErrorKind::AddressOutOfBounds(value));
+    let mut seed = now().as_nanos() as u64 | 1;
+
+    host::Host::new()
+        .with_call(0, |call| {
+            let text = call.read_str(call.args())?;
+            print!("{}", text);
+            stdout().flush().unwrap();
+
+            Ok(text.len() as isize)
+        })
+        .with_call(1, move |_| Ok(now().as_millis() as isize))
+        .with_call(2, move |call| {
+            let bound = call.arg(0)?;
+            if bound <= 0 {
+                return Ok(0);
+            }
+
+            // xorshift64
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+
+            Ok((seed % bound as u64) as isize)
+        })
+        .with_call(3, move |call| {
+            let name = address(call.arg(0)?)?;
+            let name = call.read_str(name)?;
+            let start = address(call.arg(1)?)?;
+
+            match std::fs::read(&name) {
+                Ok(bytes) => call
+                    .write_bytes(start, &bytes)
+                    .map(|_| bytes.len() as isize),
+                Err(_) => Ok(-1),
+            }
+        })
+}
+
+/// Lines typed on stdin. Stdout is flushed before each one is read, so a
+/// prompt without a newline shows up before waiting on the user.
+fn typed_lines(stdin: &Stdin) -> impl Iterator<Item = String> + '_ {
+    let mut lines = stdin.lock().lines();
+
+    std::iter::from_fn(move || {
+        stdout().flush().ok()?;
+        lines.next()?.ok()
+    })
+}
+
+/// Runs an ASCII program until it halts or stdin is closed, printing its
+/// output as text with any non-ASCII values as numbers.
+fn interact<R: Source>(mut machine: IntcodeMachine<R, ()>) {
+    let mut stdout = stdout();
+
+    loop {
+        match machine.run_until_event() {
+            Ok(Event::Output(value)) => match ascii::to_char(value) {
+                Some(c) => print!("{}", c),
+                None => println!("{}", value),
+            },
+            // Stdin has been closed.
+            Ok(Event::NeedsInput) | Ok(Event::Halted) => break,
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    stdout.flush().unwrap();
+}
+
+fn run(args: &[String]) {
+    let mut resume = None;
+    let mut save = None;
+    let mut format = SnapshotFormat::Binary;
+    let mut tracer: Box<dyn Tracer> = Box::new(NoTracer);
+    let mut profile = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--resume" => resume = Some(args.next().unwrap_or_else(|| usage())),
+            "--save" => save = Some(args.next().unwrap_or_else(|| usage())),
+            "--text" => format = SnapshotFormat::Text,
+            "--trace" => tracer = Box::new(create(args.next(), |p| trace::TextTracer::create(p))),
+            "--trace-json" => {
+                tracer = Box::new(create(args.next(), |p| trace::JsonTracer::create(p)))
+            }
+            "--profile" => profile = Some(args.next().unwrap_or_else(|| usage())),
+            _ => usage(),
+        }
+    }
+
+    let mut profiler = profile::Profiler::new();
+    let tracer: Box<dyn Tracer + '_> = match profile {
+        Some(_) => Box::new((tracer, &mut profiler)),
+        None => tracer,
+    };
+
     let stdin = stdin();
     let stdin = stdin.lock();
     let mut stdout = stdout();
+    let input = stdin.lines().filter_map(|l| l.ok()?.parse::<isize>().ok());
+
+    let mut machine = match resume {
+        Some(path) => {
+            let state = MachineState::load(path).unwrap_or_else(|e| {
+                eprintln!("couldn't load {}: {}", path, e);
+                std::process::exit(1);
+            });
+
+            IntcodeMachine::from_state(state, input, &mut stdout)
+        }
+        None => IntcodeMachine::new(&program(), input, &mut stdout),
+    }
+    .with_tracer(tracer);
+
+    // Once stdin runs dry the machine pauses rather than faulting, so it can
+    // be saved and picked up again with `--resume`.
+    let faulted = loop {
+        match machine.run_until_event() {
+            Ok(Event::Output(_)) => {}
+            Ok(Event::Halted) => break false,
+            Ok(Event::NeedsInput) => {
+                if let Some(path) = &save {
+                    machine.snapshot().save(path, format).unwrap_or_else(|e| {
+                        eprintln!("couldn't save {}: {}", path, e);
+                        std::process::exit(1);
+                    });
+                }
+
+                break false;
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                break true;
+            }
+        }
+    };
+
+    // Dropping the machine flushes the trace and releases the profiler.
+    drop(machine);
+
+    if let Some(path) = profile {
+        eprint!("{}", profiler.report(10));
+        let file = create(Some(path), |p| std::fs::File::create(p));
+        profiler
+            .write_json(std::io::BufWriter::new(file))
+            .unwrap_or_else(|e| {
+                eprintln!("couldn't write {}: {}", path, e);
+                std::process::exit(1);
+            });
+    }
+
+    if faulted {
+        std::process::exit(1);
+    }
+}
+
+fn create<T>(path: Option<&String>, create: impl Fn(&str) -> std::io::Result<T>) -> T {
+    let path = path.unwrap_or_else(|| usage());
+
+    create(path).unwrap_or_else(|e| {
+        eprintln!("couldn't create {}: {}", path, e);
+        std::process::exit(1);
+    })
+}
 
-    let mut program: Vec<isize> = vec![
-        01005, 0, 512, // jit pos(0), imm(512)
-    ];
-
-    program.resize(4096, 0);
-
-    #[rustfmt::skip]
-    let input_program = [
-        01101, 0, 0, 0,             // add imm(0), imm(0), pos(0),
-        01101, 0, 0, 2,             // add imm(0), imm(0), pos(2),
-        00003, 1000,                // input pos(1000)
-        20101, 0, 1000, 0,          // add imm(0), pos(1000), rel(0)
-        00109, 1,                   // arel imm(1)
-        01007, 1000, 99999, 1001,   // lt pos(90), imm(99999), pos(1001)
-        01006, 1001, 0,             // jif pos(1001), imm(0)
-        01005, 1001, 520,           // jit pos(1001), imm(520)
-    ];
-
-    program[512..(512 + input_program.len())].copy_from_slice(&input_program);
-
-    let mut machine = IntcodeMachine::new(
-        &program,
-        stdin.lines().filter_map(|l| l.ok()?.parse::<isize>().ok()),
-        &mut stdout,
-    );
-
-    machine.run();
+fn program() -> Vec<isize> {
+    asm::assemble(PROGRAM).unwrap()
 }